@@ -0,0 +1,26 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Write the versions of the migrations embedded by `embed_migrations!` to
+/// `$OUT_DIR/migration_versions.rs`, named the way diesel names them
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    let mut versions = fs::read_dir("migrations")
+        .expect("Failed to read the migrations directory")
+        .map(|entry| entry.expect("Failed to read the migrations directory"))
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .map(|name| name.split('_').next().unwrap_or_default().replace('-', ""))
+        .collect::<Vec<_>>();
+    versions.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migration_versions.rs");
+    fs::write(
+        out,
+        format!("const MIGRATION_VERSIONS: &[&str] = &{:?};\n", versions),
+    )
+    .expect("Failed to write migration_versions.rs");
+}
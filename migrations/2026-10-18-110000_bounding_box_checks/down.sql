@@ -0,0 +1,4 @@
+ALTER TABLE images DROP CONSTRAINT images_box_check;
+ALTER TABLE cinemas_movies DROP CONSTRAINT cinemas_movies_pixels_box_check;
+ALTER TABLE movies DROP CONSTRAINT movies_pixels_box_check;
+DROP FUNCTION valid_bounding_box(FLOAT[]);
@@ -0,0 +1,45 @@
+-- Boxes are {xmin, ymin, xmax, ymax} with finite coordinates and min <= max
+CREATE FUNCTION valid_bounding_box(b FLOAT[]) RETURNS BOOLEAN AS $$
+  SELECT b IS NULL OR COALESCE(
+    array_ndims(b) = 1
+    AND array_lower(b, 1) = 1
+    AND array_length(b, 1) = 4
+    AND b[1] <= b[3]
+    AND b[2] <= b[4]
+    -- NaN sorts above every number in postgres
+    AND b[1] > '-Infinity' AND b[2] > '-Infinity'
+    AND b[3] < 'Infinity' AND b[4] < 'Infinity',
+    FALSE
+  )
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Stored boxes are left as they are, fix or clear the listed rows and run
+-- the migration again
+DO $$
+DECLARE
+  invalid TEXT;
+BEGIN
+  SELECT string_agg(rows, '; ') INTO invalid FROM (
+    SELECT 'movies ' || string_agg(id::TEXT, ', ' ORDER BY id) AS rows
+      FROM movies WHERE NOT valid_bounding_box(pixels_box)
+    UNION ALL
+    SELECT 'cinemas_movies ' || string_agg(id::TEXT, ', ' ORDER BY id)
+      FROM cinemas_movies WHERE NOT valid_bounding_box(pixels_box)
+    UNION ALL
+    SELECT 'images ' || string_agg(id::TEXT, ', ' ORDER BY id)
+      FROM images WHERE NOT valid_bounding_box(box)
+  ) AS t;
+  IF invalid IS NOT NULL THEN
+    RAISE EXCEPTION 'invalid bounding boxes, fix or clear them first: %', invalid;
+  END IF;
+END
+$$;
+
+ALTER TABLE movies ADD CONSTRAINT movies_pixels_box_check
+  CHECK (valid_bounding_box(pixels_box));
+
+ALTER TABLE cinemas_movies ADD CONSTRAINT cinemas_movies_pixels_box_check
+  CHECK (valid_bounding_box(pixels_box));
+
+ALTER TABLE images ADD CONSTRAINT images_box_check
+  CHECK (valid_bounding_box(box));
@@ -0,0 +1,3 @@
+DROP VIEW movie_contents;
+ALTER TABLE vector_movies DROP COLUMN movie_id;
+ALTER TABLE color_movies DROP COLUMN movie_id;
@@ -0,0 +1,16 @@
+-- A color or vector movie holds the frames or data of at most one movie
+ALTER TABLE color_movies
+  ADD COLUMN movie_id INTEGER UNIQUE REFERENCES movies ON DELETE CASCADE;
+ALTER TABLE vector_movies
+  ADD COLUMN movie_id INTEGER UNIQUE REFERENCES movies ON DELETE CASCADE;
+
+-- One row per movie having content, keyed by the movie id
+CREATE VIEW movie_contents AS
+  SELECT
+    movies.id,
+    color_movies.id AS color_movie_id,
+    vector_movies.id AS vector_movie_id
+  FROM movies
+  LEFT JOIN color_movies ON color_movies.movie_id = movies.id
+  LEFT JOIN vector_movies ON vector_movies.movie_id = movies.id
+  WHERE color_movies.id IS NOT NULL OR vector_movies.id IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP INDEX vector_data_vector_movie_id_time_idx;
+
+DROP INDEX images_color_movie_id_time_idx;
@@ -0,0 +1,4 @@
+-- Frames are looked up by time within a movie
+CREATE INDEX images_color_movie_id_time_idx ON images (color_movie_id, time);
+
+CREATE INDEX vector_data_vector_movie_id_time_idx ON vector_data (vector_movie_id, time);
@@ -0,0 +1,5 @@
+DROP INDEX images_color_movie_id_time_id_idx;
+CREATE INDEX images_color_movie_id_time_idx ON images (color_movie_id, time);
+
+DROP INDEX vector_data_vector_movie_id_time_id_idx;
+CREATE INDEX vector_data_vector_movie_id_time_idx ON vector_data (vector_movie_id, time);
@@ -0,0 +1,6 @@
+-- Frame lookups order frames of a movie by time then id
+DROP INDEX images_color_movie_id_time_idx;
+CREATE INDEX images_color_movie_id_time_id_idx ON images (color_movie_id, time, id);
+
+DROP INDEX vector_data_vector_movie_id_time_idx;
+CREATE INDEX vector_data_vector_movie_id_time_id_idx ON vector_data (vector_movie_id, time, id);
@@ -0,0 +1,596 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::serialize::{self, ToSql};
+use diesel::sql_types::{Array, Bool, Double, Float8, Nullable};
+use diesel::{Column, QueryResult};
+use indexmap::IndexMap;
+use juniper::meta::Argument;
+use juniper::parser::{ParseError, Token};
+use juniper::{
+    FromInputValue, InputValue, LookAheadValue, ParseScalarResult, Registry, ToInputValue, Value,
+};
+use std::fmt;
+use std::io::Write;
+use std::marker::PhantomData;
+use wundergraph::juniper_ext::{FromLookAheadValue, NameBuilder, Nameable};
+use wundergraph::query_builder::selection::filter::{
+    AsColumnFilter, BuildFilter, FilterOption, FilterValue, InnerFilter,
+};
+use wundergraph::query_builder::types::{PlaceHolder, WundergraphValue};
+use wundergraph::scalar::WundergraphScalarValue;
+
+const COORDINATES: [&str; 4] = ["xmin", "ymin", "xmax", "ymax"];
+
+/// Region of interest stored as a `FLOAT[]` of `{xmin, ymin, xmax, ymax}`
+///
+/// Min coordinates are never greater than max ones, which the check
+/// constraints of the columns also enforce.
+///
+/// Fields resolve it as the `BoundingBox` scalar holding an object with the
+/// four coordinates. Wundergraph types the `eq`, `not_eq` and `eq_any`
+/// filters of a nullable column with the type of the field, which must then
+/// be usable as input as well, ruling out a GraphQL object. Mutations and
+/// the spatial filters take a `BoundingBoxInput` object.
+#[derive(Debug, Clone, Copy, PartialEq, AsExpression, FromSqlRow)]
+#[sql_type = "Array<Float8>"]
+pub struct BoundingBox {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BoundingBoxError {
+    Length(usize),
+    Inverted(&'static str, f64, f64),
+    NotFinite,
+}
+
+impl fmt::Display for BoundingBoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundingBoxError::Length(len) => write!(
+                f,
+                "Invalid bounding box: expected 4 values (xmin, ymin, xmax, ymax), got {}",
+                len
+            ),
+            BoundingBoxError::Inverted(axis, min, max) => write!(
+                f,
+                "Invalid bounding box: {axis}min ({}) is greater than {axis}max ({})",
+                min,
+                max,
+                axis = axis
+            ),
+            BoundingBoxError::NotFinite => {
+                write!(f, "Invalid bounding box: coordinates must be finite")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BoundingBoxError {}
+
+impl BoundingBox {
+    pub fn new(xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> Result<Self, BoundingBoxError> {
+        if ![xmin, ymin, xmax, ymax].iter().all(|v| v.is_finite()) {
+            return Err(BoundingBoxError::NotFinite);
+        }
+        if xmin > xmax {
+            return Err(BoundingBoxError::Inverted("x", xmin, xmax));
+        }
+        if ymin > ymax {
+            return Err(BoundingBoxError::Inverted("y", ymin, ymax));
+        }
+        Ok(Self {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        })
+    }
+
+    pub fn from_slice(values: &[f64]) -> Result<Self, BoundingBoxError> {
+        match *values {
+            [xmin, ymin, xmax, ymax] => Self::new(xmin, ymin, xmax, ymax),
+            _ => Err(BoundingBoxError::Length(values.len())),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<f64> {
+        vec![self.xmin, self.ymin, self.xmax, self.ymax]
+    }
+
+    pub fn width(&self) -> f64 {
+        self.xmax - self.xmin
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ymax - self.ymin
+    }
+}
+
+/// Boxes are checked again before being written, values built in Rust
+/// don't go through `BoundingBox::new`
+impl ToSql<Array<Float8>, Pg> for BoundingBox {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<'_, W, Pg>) -> serialize::Result {
+        let bbox = Self::from_slice(&self.to_vec())?;
+        ToSql::<Array<Float8>, Pg>::to_sql(&bbox.to_vec(), out)
+    }
+}
+
+impl FromSql<Array<Float8>, Pg> for BoundingBox {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let values = <Vec<f64> as FromSql<Array<Float8>, Pg>>::from_sql(bytes)?;
+        Ok(Self::from_slice(&values)?)
+    }
+}
+
+impl WundergraphValue for BoundingBox {
+    type PlaceHolder = PlaceHolder<Self>;
+    type SqlType = Nullable<Array<Float8>>;
+}
+
+impl<C> FilterValue<C> for BoundingBox {
+    type RawValue = Self;
+    type AdditionalFilter = BoundingBoxFilter<C>;
+}
+
+impl<C, DB, Ctx> AsColumnFilter<C, DB, Ctx> for BoundingBox {
+    type Filter = FilterOption<Self, C>;
+}
+
+impl Nameable for BoundingBox {
+    fn name() -> String {
+        String::from("BoundingBox")
+    }
+}
+
+impl FromLookAheadValue for BoundingBox {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        if let LookAheadValue::Object(ref fields) = *v {
+            if fields.len() != COORDINATES.len() {
+                return None;
+            }
+            let coord = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| *field == name)
+                    .and_then(|(_, value)| f64::from_look_ahead(value))
+            };
+            Self::new(
+                coord("xmin")?,
+                coord("ymin")?,
+                coord("xmax")?,
+                coord("ymax")?,
+            )
+            .ok()
+        } else {
+            None
+        }
+    }
+}
+
+graphql_scalar!(BoundingBox as "BoundingBox" where Scalar = WundergraphScalarValue {
+    description: "Rectangle given as `{ xmin, ymin, xmax, ymax }`, min coordinates must not be \
+                  greater than max ones"
+
+    resolve(&self) -> Value {
+        Value::object(
+            COORDINATES
+                .iter()
+                .zip(self.to_vec())
+                .map(|(name, v)| (*name, Value::scalar(v)))
+                .collect(),
+        )
+    }
+
+    from_input_value(v: &InputValue) -> Option<BoundingBox> {
+        let fields = v.to_object_value()?;
+        if fields.len() != COORDINATES.len() {
+            return None;
+        }
+        let coord = |name: &str| fields.get(name).and_then(|v| f64::from_input_value(v));
+        BoundingBox::new(coord("xmin")?, coord("ymin")?, coord("xmax")?, coord("ymax")?).ok()
+    }
+
+    from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, WundergraphScalarValue> {
+        Err(ParseError::UnexpectedToken(Token::Scalar(value)))
+    }
+});
+
+/// A `BoundingBox` given as argument
+///
+/// Written boxes are validated when converted into a `BoundingBox`, the
+/// filters use the coordinates as given.
+#[derive(Debug, Clone, Copy, PartialEq, AsExpression, juniper::GraphQLInputObject)]
+#[graphql(scalar = "WundergraphScalarValue")]
+#[sql_type = "Array<Float8>"]
+pub struct BoundingBoxInput {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+impl BoundingBoxInput {
+    pub fn validate(&self) -> Result<BoundingBox, BoundingBoxError> {
+        BoundingBox::new(self.xmin, self.ymin, self.xmax, self.ymax)
+    }
+}
+
+/// Invalid boxes fail the mutation with the message of the
+/// `BoundingBoxError`
+impl ToSql<Array<Float8>, Pg> for BoundingBoxInput {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<'_, W, Pg>) -> serialize::Result {
+        ToSql::<Array<Float8>, Pg>::to_sql(&self.validate()?, out)
+    }
+}
+
+impl FromLookAheadValue for BoundingBoxInput {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        if let LookAheadValue::Object(ref fields) = *v {
+            let coord = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| *field == name)
+                    .and_then(|(_, value)| f64::from_look_ahead(value))
+            };
+            Some(Self {
+                xmin: coord("xmin")?,
+                ymin: coord("ymin")?,
+                xmax: coord("xmax")?,
+                ymax: coord("ymax")?,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Spatial operators added to the filter of `BoundingBox` fields
+///
+/// * `contains`: the stored box contains the given one
+/// * `within`: the stored box is inside the given one
+/// * `intersects`: both boxes share at least one point
+#[derive(Debug, Clone)]
+pub struct BoundingBoxFilter<C> {
+    contains: Option<BoundingBoxInput>,
+    within: Option<BoundingBoxInput>,
+    intersects: Option<BoundingBoxInput>,
+    column: PhantomData<C>,
+}
+
+impl<C> Nameable for BoundingBoxFilter<C> {
+    fn name() -> String {
+        String::from("BoundingBoxFilter")
+    }
+}
+
+impl<C> InnerFilter for BoundingBoxFilter<C> {
+    type Context = ();
+
+    const FIELD_COUNT: usize = 3;
+
+    fn from_inner_input_value(
+        obj: IndexMap<&str, &InputValue<WundergraphScalarValue>>,
+    ) -> Option<Self> {
+        let arg = |name: &str| match obj.get(name) {
+            Some(v) => Option::<BoundingBoxInput>::from_input_value(*v),
+            None => Some(None),
+        };
+        Some(Self {
+            contains: arg("contains")?,
+            within: arg("within")?,
+            intersects: arg("intersects")?,
+            column: PhantomData,
+        })
+    }
+
+    fn from_inner_look_ahead(obj: &[(&str, LookAheadValue<'_, WundergraphScalarValue>)]) -> Self {
+        let arg = |name: &str| {
+            obj.iter()
+                .find(|(field, _)| *field == name)
+                .and_then(|(_, value)| BoundingBoxInput::from_look_ahead(value))
+        };
+        Self {
+            contains: arg("contains"),
+            within: arg("within"),
+            intersects: arg("intersects"),
+            column: PhantomData,
+        }
+    }
+
+    fn to_inner_input_value(&self, v: &mut IndexMap<&str, InputValue<WundergraphScalarValue>>) {
+        v.insert("contains", self.contains.to_input_value());
+        v.insert("within", self.within.to_input_value());
+        v.insert("intersects", self.intersects.to_input_value());
+    }
+
+    fn register_fields<'r>(
+        _info: &NameBuilder<Self>,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> Vec<Argument<'r, WundergraphScalarValue>> {
+        let contains = registry
+            .arg::<Option<BoundingBoxInput>>("contains", &())
+            .description("The box contains this one");
+        let within = registry
+            .arg::<Option<BoundingBoxInput>>("within", &())
+            .description("The box is inside this one");
+        let intersects = registry
+            .arg::<Option<BoundingBoxInput>>("intersects", &())
+            .description("The box shares at least one point with this one");
+        vec![contains, within, intersects]
+    }
+}
+
+impl<C> BuildFilter<Pg> for BoundingBoxFilter<C>
+where
+    C: Column + Default + QueryFragment<Pg> + NonAggregate,
+{
+    type Ret = BoundingBoxPredicate<C>;
+
+    fn into_filter(self) -> Option<Self::Ret> {
+        let mut conditions = Vec::new();
+        if let Some(b) = self.contains {
+            conditions.push((BoxRelation::Contains, b));
+        }
+        if let Some(b) = self.within {
+            conditions.push((BoxRelation::Within, b));
+        }
+        if let Some(b) = self.intersects {
+            conditions.push((BoxRelation::Intersects, b));
+        }
+        if conditions.is_empty() {
+            None
+        } else {
+            Some(BoundingBoxPredicate {
+                column: C::default(),
+                conditions,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BoxRelation {
+    Contains,
+    Within,
+    Intersects,
+}
+
+impl BoxRelation {
+    /// `(stored index, operator, given coordinate)` comparisons, indices
+    /// being those of the postgres array
+    fn comparisons(self, b: &BoundingBoxInput) -> [(u8, &'static str, f64); 4] {
+        match self {
+            BoxRelation::Contains => [
+                (1, " <= ", b.xmin),
+                (2, " <= ", b.ymin),
+                (3, " >= ", b.xmax),
+                (4, " >= ", b.ymax),
+            ],
+            BoxRelation::Within => [
+                (1, " >= ", b.xmin),
+                (2, " >= ", b.ymin),
+                (3, " <= ", b.xmax),
+                (4, " <= ", b.ymax),
+            ],
+            BoxRelation::Intersects => [
+                (1, " <= ", b.xmax),
+                (2, " <= ", b.ymax),
+                (3, " >= ", b.xmin),
+                (4, " >= ", b.ymin),
+            ],
+        }
+    }
+}
+
+/// Conjunction of spatial conditions on a bounding box column
+#[derive(Debug, Clone)]
+pub struct BoundingBoxPredicate<C> {
+    column: C,
+    conditions: Vec<(BoxRelation, BoundingBoxInput)>,
+}
+
+impl<C> Expression for BoundingBoxPredicate<C> {
+    type SqlType = Bool;
+}
+
+impl<C> NonAggregate for BoundingBoxPredicate<C> {}
+
+impl<C, QS> AppearsOnTable<QS> for BoundingBoxPredicate<C> where C: AppearsOnTable<QS> {}
+
+impl<C, QS> SelectableExpression<QS> for BoundingBoxPredicate<C> where C: SelectableExpression<QS> {}
+
+impl<C> QueryId for BoundingBoxPredicate<C> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<C> QueryFragment<Pg> for BoundingBoxPredicate<C>
+where
+    C: QueryFragment<Pg>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        out.push_sql("(");
+        let comparisons = self
+            .conditions
+            .iter()
+            .flat_map(|(relation, b)| relation.comparisons(b).to_vec());
+        for (i, (index, op, value)) in comparisons.enumerate() {
+            if i > 0 {
+                out.push_sql(" AND ");
+            }
+            self.column.walk_ast(out.reborrow())?;
+            out.push_sql(&format!("[{}]", index));
+            out.push_sql(op);
+            out.push_bind_param::<Double, _>(&value)?;
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::movies;
+    use diesel::{debug_query, QueryDsl};
+
+    fn input(value: &str) -> InputValue<WundergraphScalarValue> {
+        serde_json::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn new() {
+        let b = BoundingBox::new(1.0, 2.0, 11.0, 7.0).unwrap();
+        assert!((b.width() - 10.0).abs() < f64::EPSILON);
+        assert!((b.height() - 5.0).abs() < f64::EPSILON);
+        assert_eq!(b.to_vec(), vec![1.0, 2.0, 11.0, 7.0]);
+        // Empty boxes are allowed
+        assert!(BoundingBox::new(1.0, 1.0, 1.0, 1.0).is_ok());
+    }
+
+    #[test]
+    fn invalid_boxes() {
+        assert_eq!(
+            BoundingBox::new(3.0, 0.0, 1.0, 1.0),
+            Err(BoundingBoxError::Inverted("x", 3.0, 1.0))
+        );
+        assert_eq!(
+            BoundingBox::new(0.0, 3.0, 1.0, 1.0),
+            Err(BoundingBoxError::Inverted("y", 3.0, 1.0))
+        );
+        assert_eq!(
+            BoundingBox::new(0.0, 0.0, std::f64::NAN, 1.0),
+            Err(BoundingBoxError::NotFinite)
+        );
+        assert_eq!(
+            BoundingBox::new(std::f64::NEG_INFINITY, 0.0, 1.0, 1.0),
+            Err(BoundingBoxError::NotFinite)
+        );
+        assert_eq!(
+            BoundingBox::from_slice(&[0.0, 0.0, 1.0]),
+            Err(BoundingBoxError::Length(3))
+        );
+        assert_eq!(
+            BoundingBoxError::Inverted("x", 3.0, 1.0).to_string(),
+            "Invalid bounding box: xmin (3) is greater than xmax (1)"
+        );
+    }
+
+    #[test]
+    fn parse_input() {
+        let b = BoundingBoxInput::from_input_value(&input(
+            r#"{"xmin": 1, "ymin": 2.5, "xmax": 3, "ymax": 4}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            b.validate(),
+            Ok(BoundingBox {
+                xmin: 1.0,
+                ymin: 2.5,
+                xmax: 3.0,
+                ymax: 4.0
+            })
+        );
+
+        // Inverted boxes parse, they are rejected with a message when written
+        let b = BoundingBoxInput::from_input_value(&input(
+            r#"{"xmin": 3, "ymin": 0, "xmax": 1, "ymax": 1}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            b.validate().unwrap_err().to_string(),
+            "Invalid bounding box: xmin (3) is greater than xmax (1)"
+        );
+
+        assert!(BoundingBoxInput::from_input_value(&input("[0, 0, 1, 1]")).is_none());
+    }
+
+    #[test]
+    fn parse_scalar() {
+        let b = BoundingBox::from_input_value(&input(
+            r#"{"xmin": 0, "ymin": 0, "xmax": 1.5, "ymax": 1}"#,
+        ));
+        assert_eq!(b, Some(BoundingBox::new(0.0, 0.0, 1.5, 1.0).unwrap()));
+        assert!(BoundingBox::from_input_value(&input(
+            r#"{"xmin": 3, "ymin": 0, "xmax": 1, "ymax": 1}"#
+        ))
+        .is_none());
+        assert!(BoundingBox::from_input_value(&input(
+            r#"{"xmin": 0, "ymin": 0, "xmax": 1, "ymax": 1, "zmax": 1}"#
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn parse_filter() {
+        let filter = BoundingBoxFilter::<movies::pixels_box>::from_inner_input_value(
+            input(r#"{"within": {"xmin": 0, "ymin": 0, "xmax": 10, "ymax": 10}}"#)
+                .to_object_value()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(filter.contains, None);
+        assert_eq!(
+            filter.within,
+            Some(BoundingBoxInput {
+                xmin: 0.0,
+                ymin: 0.0,
+                xmax: 10.0,
+                ymax: 10.0
+            })
+        );
+
+        let filter = BoundingBoxFilter::<movies::pixels_box>::from_inner_input_value(
+            input("{}").to_object_value().unwrap(),
+        )
+        .unwrap();
+        assert!(filter.into_filter().is_none());
+        assert!(
+            BoundingBoxFilter::<movies::pixels_box>::from_inner_input_value(
+                input(r#"{"within": [0, 0, 10, 10]}"#)
+                    .to_object_value()
+                    .unwrap()
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn filter_sql() {
+        let b = BoundingBoxInput {
+            xmin: 1.0,
+            ymin: 2.0,
+            xmax: 3.0,
+            ymax: 4.0,
+        };
+        let filter = BoundingBoxFilter::<movies::pixels_box> {
+            contains: Some(b),
+            within: None,
+            intersects: Some(b),
+            column: PhantomData,
+        };
+        let query = movies::table
+            .select(movies::id)
+            .filter(filter.into_filter().unwrap());
+        let sql = debug_query::<Pg, _>(&query).to_string();
+        let column = "\"movies\".\"pixels_box\"";
+        let expected = format!(
+            "WHERE ({c}[1] <= $1 AND {c}[2] <= $2 AND {c}[3] >= $3 AND {c}[4] >= $4 \
+             AND {c}[1] <= $5 AND {c}[2] <= $6 AND {c}[3] >= $7 AND {c}[4] >= $8)",
+            c = column
+        );
+        assert!(sql.contains(&expected), "{}", sql);
+        assert!(
+            sql.ends_with("binds: [1.0, 2.0, 3.0, 4.0, 3.0, 4.0, 1.0, 2.0]"),
+            "{}",
+            sql
+        );
+    }
+}
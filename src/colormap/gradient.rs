@@ -0,0 +1,534 @@
+use super::{parse_hex_color, validate, ColormapError};
+use crate::generated::colormaps;
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::sql_types::{Array, Bool, Float8, Nullable, Record, Text};
+use diesel::QueryResult;
+use indexmap::IndexMap;
+use juniper::meta::{Argument, MetaType};
+use juniper::{
+    FieldError, FieldResult, FromInputValue, GraphQLType, InputValue, LookAheadValue, Registry,
+    ToInputValue,
+};
+use std::marker::PhantomData;
+use wundergraph::diesel_ext::BoxableFilter;
+use wundergraph::juniper_ext::{FromLookAheadValue, NameBuilder, Nameable};
+use wundergraph::query_builder::selection::filter::{AsColumnFilter, BuildFilter, InnerFilter};
+use wundergraph::query_builder::types::{PlaceHolder, WundergraphValue};
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Upper bound of `Gradient.sample(n)`
+const MAX_SAMPLES: i32 = 4096;
+
+/// SQL type of `gradient`, the colors and positions of a colormap as a
+/// record
+pub type GradientSql = Record<(Array<Text>, Array<Float8>)>;
+
+/// The columns of `colormaps` and the computed `gradient`, the
+/// `table_name` of the `Colormap` entity
+pub mod colormap_columns {
+    pub use crate::generated::colormaps::*;
+
+    use crate::generated::colormaps;
+    use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+    use diesel::pg::Pg;
+    use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+    use diesel::{Column, QueryResult};
+
+    /// `ROW(colormaps.colors, colormaps.positions)`, loaded into the
+    /// `Colormap.gradient` field
+    ///
+    /// Not a column of `colormaps`, nothing is stored: the stops are read
+    /// as one value and interpolated when the field is resolved.
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct gradient;
+
+    impl Expression for gradient {
+        type SqlType = super::GradientSql;
+    }
+
+    impl NonAggregate for gradient {}
+
+    impl<QS> AppearsOnTable<QS> for gradient where
+        (colormaps::colors, colormaps::positions): AppearsOnTable<QS>
+    {
+    }
+
+    impl<QS> SelectableExpression<QS> for gradient where
+        (colormaps::colors, colormaps::positions): SelectableExpression<QS>
+    {
+    }
+
+    impl Column for gradient {
+        type Table = colormaps::table;
+
+        const NAME: &'static str = "gradient";
+    }
+
+    impl QueryId for gradient {
+        type QueryId = Self;
+
+        const HAS_STATIC_QUERY_ID: bool = true;
+    }
+
+    impl QueryFragment<Pg> for gradient {
+        fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+            out.push_sql("ROW(");
+            colormaps::colors.walk_ast(out.reborrow())?;
+            out.push_sql(", ");
+            colormaps::positions.walk_ast(out.reborrow())?;
+            out.push_sql(")");
+            Ok(())
+        }
+    }
+}
+
+/// Color stops of a valid colormap, interpolated linearly in RGBA
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f64, [u8; 4])>,
+}
+
+impl Gradient {
+    pub fn new(colors: &[String], positions: &[f64]) -> Result<Self, ColormapError> {
+        validate(colors, positions)?;
+        let stops = positions
+            .iter()
+            .zip(colors)
+            .map(|(p, c)| (*p, parse_hex_color(c).expect("Colors are validated")))
+            .collect();
+        Ok(Gradient { stops })
+    }
+
+    /// `(position, rgba)` stops, by increasing position
+    pub fn stops(&self) -> &[(f64, [u8; 4])] {
+        &self.stops
+    }
+
+    /// Color at `value` within `[0, 1]`, values outside take the color of
+    /// the closest end
+    pub fn color_at(&self, value: f64) -> [u8; 4] {
+        let (first, last) = (self.stops[0], self.stops[self.stops.len() - 1]);
+        if value.is_nan() || value <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((p0, c0), (p1, c1)) = (pair[0], pair[1]);
+            if value <= p1 {
+                if p1 <= p0 {
+                    return c1;
+                }
+                return lerp(c0, c1, (value - p0) / (p1 - p0));
+            }
+        }
+        last.1
+    }
+
+    /// `n` colors evenly spaced from 0 to 1, both included
+    pub fn sample(&self, n: usize) -> Vec<[u8; 4]> {
+        (0..n)
+            .map(|i| match n {
+                1 => self.color_at(0.0),
+                _ => self.color_at(i as f64 / (n - 1) as f64),
+            })
+            .collect()
+    }
+
+    /// The same gradient running from 1 to 0
+    pub fn reversed(&self) -> Self {
+        let stops = self
+            .stops
+            .iter()
+            .rev()
+            .map(|(p, c)| (1.0 - p, *c))
+            .collect();
+        Gradient { stops }
+    }
+}
+
+fn lerp(from: [u8; 4], to: [u8; 4], t: f64) -> [u8; 4] {
+    let mut color = [0; 4];
+    for (i, c) in color.iter_mut().enumerate() {
+        let (a, b) = (f64::from(from[i]), f64::from(to[i]));
+        *c = (a + (b - a) * t).round() as u8;
+    }
+    color
+}
+
+/// `#rrggbb`, or `#rrggbbaa` for translucent colors
+pub fn to_hex(color: [u8; 4]) -> String {
+    let [r, g, b, a] = color;
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+/// `Colormap.gradient`, the colors and positions as stored
+///
+/// Read as they are so a colormap stored before validation existed still
+/// loads, they are validated when a field of the gradient is resolved and a
+/// field error is returned if they are invalid. Wundergraph entity fields
+/// can't take arguments, the computed fields are on this object
+/// (`Colormap.gradient { sample(n: 5) }`).
+#[derive(Debug, Clone, PartialEq, FromSqlRow)]
+pub struct StoredGradient {
+    colors: Vec<String>,
+    positions: Vec<f64>,
+}
+
+impl StoredGradient {
+    pub fn new(colors: Vec<String>, positions: Vec<f64>) -> Self {
+        StoredGradient { colors, positions }
+    }
+
+    pub fn gradient(&self) -> Result<Gradient, ColormapError> {
+        Gradient::new(&self.colors, &self.positions)
+    }
+
+    fn valid(&self) -> FieldResult<Gradient, WundergraphScalarValue> {
+        self.gradient().map_err(|e| e.to_field_error(None))
+    }
+}
+
+impl FromSql<GradientSql, Pg> for StoredGradient {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let (colors, positions) =
+            <(Vec<String>, Vec<f64>) as FromSql<GradientSql, Pg>>::from_sql(bytes)?;
+        Ok(StoredGradient { colors, positions })
+    }
+}
+
+impl WundergraphValue for StoredGradient {
+    type PlaceHolder = PlaceHolder<Self>;
+    type SqlType = Nullable<GradientSql>;
+}
+
+impl<C, DB, Ctx> AsColumnFilter<C, DB, Ctx> for StoredGradient {
+    type Filter = GradientFilter<C>;
+}
+
+#[juniper::object(name = "Gradient", Scalar = WundergraphScalarValue)]
+impl StoredGradient {
+    /// `n` colors evenly spaced along the gradient, ends included
+    fn sample(&self, n: i32) -> FieldResult<Vec<String>, WundergraphScalarValue> {
+        if !(1..=MAX_SAMPLES).contains(&n) {
+            return Err(FieldError::new(
+                format!("n must be within [1, {}], got {}", MAX_SAMPLES, n),
+                graphql_value!({ "code": "INVALID_ARGUMENT" }),
+            ));
+        }
+        let gradient = self.valid()?;
+        Ok(gradient
+            .sample(n as usize)
+            .into_iter()
+            .map(to_hex)
+            .collect())
+    }
+
+    /// Color of `value` once `[min, max]` (default `[0, 1]`) is mapped onto
+    /// the gradient, values out of range are clamped
+    fn color_at(
+        &self,
+        value: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> FieldResult<String, WundergraphScalarValue> {
+        let (min, max) = (min.unwrap_or(0.0), max.unwrap_or(1.0));
+        if min.is_nan() || max.is_nan() || min >= max {
+            return Err(FieldError::new(
+                format!("min must be lower than max, got [{}, {}]", min, max),
+                graphql_value!({ "code": "INVALID_ARGUMENT" }),
+            ));
+        }
+        let gradient = self.valid()?;
+        Ok(to_hex(gradient.color_at((value - min) / (max - min))))
+    }
+
+    /// The same gradient running from 1 to 0
+    fn reversed(&self) -> FieldResult<StoredGradient, WundergraphScalarValue> {
+        let gradient = self.valid()?.reversed();
+        let (positions, colors) = gradient
+            .stops()
+            .iter()
+            .map(|(p, c)| (*p, to_hex(*c)))
+            .unzip();
+        Ok(StoredGradient { colors, positions })
+    }
+}
+
+/// Filter of `Gradient` fields
+///
+/// * `hasColor`: one of the stops has this color, compared case
+///   insensitively
+#[derive(Debug, Clone)]
+pub struct GradientFilter<C> {
+    has_color: Option<String>,
+    column: PhantomData<C>,
+}
+
+impl<C> Nameable for GradientFilter<C> {
+    fn name() -> String {
+        String::from("GradientFilter")
+    }
+}
+
+impl<C> InnerFilter for GradientFilter<C> {
+    type Context = ();
+
+    const FIELD_COUNT: usize = 1;
+
+    fn from_inner_input_value(
+        obj: IndexMap<&str, &InputValue<WundergraphScalarValue>>,
+    ) -> Option<Self> {
+        let has_color = match obj.get("hasColor") {
+            Some(v) => Option::<String>::from_input_value(*v)?,
+            None => None,
+        };
+        Some(Self {
+            has_color,
+            column: PhantomData,
+        })
+    }
+
+    fn from_inner_look_ahead(obj: &[(&str, LookAheadValue<'_, WundergraphScalarValue>)]) -> Self {
+        let has_color = obj
+            .iter()
+            .find(|(field, _)| *field == "hasColor")
+            .and_then(|(_, value)| String::from_look_ahead(value));
+        Self {
+            has_color,
+            column: PhantomData,
+        }
+    }
+
+    fn to_inner_input_value(&self, v: &mut IndexMap<&str, InputValue<WundergraphScalarValue>>) {
+        v.insert("hasColor", self.has_color.to_input_value());
+    }
+
+    fn register_fields<'r>(
+        _info: &NameBuilder<Self>,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> Vec<Argument<'r, WundergraphScalarValue>> {
+        let has_color = registry
+            .arg::<Option<String>>("hasColor", &())
+            .description("One of the stops has this hex color");
+        vec![has_color]
+    }
+}
+
+impl<C> FromInputValue<WundergraphScalarValue> for GradientFilter<C> {
+    fn from_input_value(v: &InputValue<WundergraphScalarValue>) -> Option<Self> {
+        Self::from_inner_input_value(v.to_object_value()?)
+    }
+}
+
+impl<C> ToInputValue<WundergraphScalarValue> for GradientFilter<C> {
+    fn to_input_value(&self) -> InputValue<WundergraphScalarValue> {
+        let mut map = IndexMap::with_capacity(Self::FIELD_COUNT);
+        self.to_inner_input_value(&mut map);
+        InputValue::object(map)
+    }
+}
+
+impl<C> FromLookAheadValue for GradientFilter<C> {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        if let LookAheadValue::Object(ref obj) = *v {
+            Some(Self::from_inner_look_ahead(obj))
+        } else {
+            None
+        }
+    }
+}
+
+impl<C> GraphQLType<WundergraphScalarValue> for GradientFilter<C> {
+    type Context = ();
+    type TypeInfo = NameBuilder<Self>;
+
+    fn name(info: &Self::TypeInfo) -> Option<&str> {
+        Some(info.name())
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let fields = Self::register_fields(&NameBuilder::default(), registry);
+        registry
+            .build_input_object_type::<Self>(info, &fields)
+            .into_meta()
+    }
+}
+
+impl BuildFilter<Pg> for GradientFilter<colormap_columns::gradient> {
+    type Ret = Box<dyn BoxableFilter<colormaps::table, Pg, SqlType = Bool>>;
+
+    fn into_filter(self) -> Option<Self::Ret> {
+        self.has_color
+            .map(|color| Box::new(HasColor { color }) as Box<_>)
+    }
+}
+
+/// `EXISTS (SELECT 1 FROM unnest(colormaps.colors) c WHERE lower(c) = lower($color))`
+#[derive(Debug, Clone)]
+pub struct HasColor {
+    color: String,
+}
+
+impl Expression for HasColor {
+    type SqlType = Bool;
+}
+
+impl NonAggregate for HasColor {}
+
+impl<QS> AppearsOnTable<QS> for HasColor where colormaps::colors: AppearsOnTable<QS> {}
+
+impl<QS> SelectableExpression<QS> for HasColor where colormaps::colors: SelectableExpression<QS> {}
+
+impl QueryId for HasColor {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl QueryFragment<Pg> for HasColor {
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        out.push_sql("EXISTS (SELECT 1 FROM unnest(");
+        colormaps::colors.walk_ast(out.reborrow())?;
+        out.push_sql(") AS stop WHERE lower(stop) = lower(");
+        out.push_bind_param::<Text, _>(&self.color)?;
+        out.push_sql("))");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::{debug_query, QueryDsl};
+
+    fn gradient(colors: &[&str], positions: &[f64]) -> Result<Gradient, ColormapError> {
+        let colors = colors.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        Gradient::new(&colors, positions)
+    }
+
+    fn hex(colors: Vec<[u8; 4]>) -> Vec<String> {
+        colors.into_iter().map(to_hex).collect()
+    }
+
+    #[test]
+    fn endpoints() {
+        let g = gradient(&["#000", "#ff000080"], &[0.2, 0.8]).unwrap();
+        assert_eq!(g.color_at(0.0), [0, 0, 0, 255]);
+        assert_eq!(g.color_at(0.2), [0, 0, 0, 255]);
+        assert_eq!(g.color_at(0.35), [64, 0, 0, 223]);
+        assert_eq!(g.color_at(0.8), [255, 0, 0, 128]);
+        assert_eq!(g.color_at(1.0), [255, 0, 0, 128]);
+        assert_eq!(g.color_at(-3.0), [0, 0, 0, 255]);
+        assert_eq!(g.color_at(3.0), [255, 0, 0, 128]);
+        assert_eq!(g.color_at(f64::NAN), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn samples() {
+        let g = gradient(&["#000000", "#ffffff"], &[0.0, 1.0]).unwrap();
+        assert_eq!(hex(g.sample(1)), vec!["#000000"]);
+        assert_eq!(hex(g.sample(2)), vec!["#000000", "#ffffff"]);
+        assert_eq!(
+            hex(g.sample(5)),
+            vec!["#000000", "#404040", "#808080", "#bfbfbf", "#ffffff"]
+        );
+        assert!(g.sample(0).is_empty());
+
+        let single = gradient(&["#123456"], &[0.5]).unwrap();
+        assert_eq!(hex(single.sample(3)), vec!["#123456"; 3]);
+    }
+
+    #[test]
+    fn hard_stop() {
+        let g = gradient(&["#000", "#f00", "#00f", "#fff"], &[0.0, 0.5, 0.5, 1.0]).unwrap();
+        assert_eq!(g.color_at(0.5), [255, 0, 0, 255]);
+        assert_eq!(g.color_at(0.500_001), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn reversed() {
+        let g = gradient(&["#000", "#f00", "#fff"], &[0.0, 0.25, 1.0]).unwrap();
+        let r = g.reversed();
+        assert_eq!(
+            r.stops(),
+            &[
+                (0.0, [255, 255, 255, 255]),
+                (0.75, [255, 0, 0, 255]),
+                (1.0, [0, 0, 0, 255])
+            ][..]
+        );
+        for i in 0..=10 {
+            let v = f64::from(i) / 10.0;
+            assert_eq!(r.color_at(v), g.color_at(1.0 - v), "at {}", v);
+        }
+        assert_eq!(r.reversed(), g);
+    }
+
+    #[test]
+    fn unsorted_stops() {
+        let error = gradient(&["#000", "#fff", "#f00"], &[0.0, 1.0, 0.5]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid colormap: positions[2]: 0.5 is lower than the previous position 1"
+        );
+    }
+
+    #[test]
+    fn stored_gradient() {
+        let stored = StoredGradient::new(vec!["#000".into(), "#fff".into()], vec![0.0, 1.0]);
+        assert_eq!(
+            stored.gradient().unwrap().color_at(0.5),
+            [128, 128, 128, 255]
+        );
+
+        let legacy = StoredGradient::new(vec!["#000".into(), "white".into()], vec![0.0]);
+        let error = legacy.valid().unwrap_err();
+        assert!(error.message().starts_with("Invalid colormap: "));
+        let extensions = serde_json::to_value(error.extensions()).unwrap();
+        assert_eq!(extensions["code"], "INVALID_COLORMAP");
+        assert_eq!(extensions["field"], "positions");
+    }
+
+    #[test]
+    fn gradient_sql() {
+        let query = colormaps::table.select(colormap_columns::gradient);
+        assert_eq!(
+            debug_query::<Pg, _>(&query).to_string(),
+            "SELECT ROW(\"colormaps\".\"colors\", \"colormaps\".\"positions\") \
+             FROM \"colormaps\" -- binds: []"
+        );
+    }
+
+    #[test]
+    fn has_color_sql() {
+        let filter = GradientFilter::<colormap_columns::gradient> {
+            has_color: Some("#ABC".into()),
+            column: PhantomData,
+        };
+        let query = colormaps::table
+            .select(colormaps::id)
+            .filter(filter.into_filter().unwrap());
+        let sql = debug_query::<Pg, _>(&query).to_string();
+        assert!(
+            sql.ends_with(
+                "WHERE EXISTS (SELECT 1 FROM unnest(\"colormaps\".\"colors\") AS stop \
+                 WHERE lower(stop) = lower($1)) -- binds: [\"#ABC\"]"
+            ),
+            "{}",
+            sql
+        );
+    }
+}
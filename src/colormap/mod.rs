@@ -0,0 +1,411 @@
+use crate::generated::{colormaps, Colormap};
+use diesel::pg::Pg;
+use juniper::{ExecutionResult, Executor, FieldError, Object, Selection, Value};
+use std::error::Error;
+use std::fmt;
+use wundergraph::query_builder::mutations::{HandleBatchInsert, HandleInsert, HandleUpdate};
+use wundergraph::scalar::WundergraphScalarValue;
+
+mod gradient;
+
+pub use self::gradient::{
+    colormap_columns, to_hex, Gradient, GradientFilter, GradientSql, HasColor, StoredGradient,
+};
+
+/// Parse a CSS hex color (`#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`) into
+/// its RGBA components
+pub fn parse_hex_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#')?;
+    if !hex.is_ascii() {
+        return None;
+    }
+    let digit = |i: usize, len: usize| u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok();
+    match hex.len() {
+        3 | 4 => {
+            let mut rgba = [255; 4];
+            for (i, c) in rgba.iter_mut().enumerate().take(hex.len()) {
+                *c = digit(i, 1)? * 17;
+            }
+            Some(rgba)
+        }
+        6 | 8 => {
+            let mut rgba = [255; 4];
+            for (i, c) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+                *c = digit(i, 2)?;
+            }
+            Some(rgba)
+        }
+        _ => None,
+    }
+}
+
+/// A problem with one field of a colormap, `field` is the GraphQL input
+/// field and `index` the position of the item for list fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: &'static str,
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "{}[{}]: {}", self.field, index, self.message),
+            None => write!(f, "{}: {}", self.field, self.message),
+        }
+    }
+}
+
+/// All the problems found with a colormap
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColormapError(pub Vec<Violation>);
+
+impl ColormapError {
+    /// Error of a rejected mutation, `item` is the position of the colormap
+    /// in a batch insert
+    ///
+    /// `extensions` has `code: "INVALID_COLORMAP"`, the `field` and `index` of
+    /// the first violation, and all of them in `violations`.
+    pub fn to_field_error(&self, item: Option<usize>) -> FieldError<WundergraphScalarValue> {
+        let mut extensions = Object::with_capacity(5);
+        extensions.add_field("code", Value::scalar("INVALID_COLORMAP"));
+        if let Some(item) = item {
+            extensions.add_field("item", Value::scalar(item as i32));
+        }
+        if let Some(first) = self.0.first() {
+            extensions.add_field("field", Value::scalar(first.field));
+            extensions.add_field("index", index_value(first.index));
+        }
+        let violations = self
+            .0
+            .iter()
+            .map(|v| {
+                let mut violation = Object::with_capacity(3);
+                violation.add_field("field", Value::scalar(v.field));
+                violation.add_field("index", index_value(v.index));
+                violation.add_field("message", Value::scalar(v.message.as_str()));
+                Value::object(violation)
+            })
+            .collect();
+        extensions.add_field("violations", Value::list(violations));
+        let message = match item {
+            Some(item) => format!("Colormap {}: {}", item, self),
+            None => self.to_string(),
+        };
+        FieldError::new(message, Value::object(extensions))
+    }
+}
+
+fn index_value(index: Option<usize>) -> Value<WundergraphScalarValue> {
+    index.map_or(Value::null(), |i| Value::scalar(i as i32))
+}
+
+impl fmt::Display for ColormapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid colormap")?;
+        for (i, v) in self.0.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { ": " } else { "; " }, v)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ColormapError {}
+
+/// Check that a colormap can be rendered: one CSS hex color per position,
+/// positions increasing within `[0, 1]`
+pub fn validate(colors: &[String], positions: &[f64]) -> Result<(), ColormapError> {
+    let mut violations = Vec::new();
+    let mut violation = |field, index, message| {
+        violations.push(Violation {
+            field,
+            index,
+            message,
+        })
+    };
+
+    if colors.is_empty() {
+        violation("colors", None, "at least one color is required".into());
+    }
+    if colors.len() != positions.len() {
+        violation(
+            "positions",
+            None,
+            format!(
+                "expected {} positions, one per color, got {}",
+                colors.len(),
+                positions.len()
+            ),
+        );
+    }
+    for (i, color) in colors.iter().enumerate() {
+        if parse_hex_color(color).is_none() {
+            violation(
+                "colors",
+                Some(i),
+                format!("{:?} is not a CSS hex color", color),
+            );
+        }
+    }
+    for (i, position) in positions.iter().enumerate() {
+        if !(0.0..=1.0).contains(position) {
+            violation(
+                "positions",
+                Some(i),
+                format!("{} is not within [0, 1]", position),
+            );
+        } else if i > 0 && *position < positions[i - 1] {
+            violation(
+                "positions",
+                Some(i),
+                format!(
+                    "{} is lower than the previous position {}",
+                    position,
+                    positions[i - 1]
+                ),
+            );
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(ColormapError(violations))
+    }
+}
+
+/// Input of `CreateColormap` and `CreateColormaps`
+///
+/// Not `Insertable` itself: the mutations validate it first, then insert the
+/// `ValidColormap` it turns into, so an invalid colormap fails with a field
+/// addressed error before any statement is sent.
+#[derive(juniper::GraphQLInputObject, Clone, Debug)]
+#[graphql(scalar = "WundergraphScalarValue")]
+pub struct NewColormap {
+    name: String,
+    colors: Vec<String>,
+    positions: Vec<f64>,
+}
+
+impl NewColormap {
+    pub fn validate(self) -> Result<ValidColormap, ColormapError> {
+        validate(&self.colors, &self.positions)?;
+        Ok(ValidColormap {
+            name: self.name,
+            colors: self.colors,
+            positions: self.positions,
+        })
+    }
+}
+
+/// A `NewColormap` that passed validation
+#[derive(Insertable, Clone, Debug)]
+#[table_name = "colormaps"]
+pub struct ValidColormap {
+    name: String,
+    colors: Vec<String>,
+    positions: Vec<f64>,
+}
+
+/// Input of `UpdateColormap`, validated like `NewColormap`
+#[derive(juniper::GraphQLInputObject, Clone, Debug)]
+#[graphql(scalar = "WundergraphScalarValue")]
+pub struct ColormapChangeset {
+    id: i32,
+    name: String,
+    colors: Vec<String>,
+    positions: Vec<f64>,
+}
+
+impl ColormapChangeset {
+    pub fn validate(&self) -> Result<ValidColormapChangeset, ColormapError> {
+        validate(&self.colors, &self.positions)?;
+        Ok(ValidColormapChangeset {
+            id: self.id,
+            name: self.name.clone(),
+            colors: self.colors.clone(),
+            positions: self.positions.clone(),
+        })
+    }
+}
+
+/// A `ColormapChangeset` that passed validation
+#[derive(AsChangeset, Identifiable, Clone, Debug)]
+#[table_name = "colormaps"]
+#[primary_key(id)]
+pub struct ValidColormapChangeset {
+    id: i32,
+    name: String,
+    colors: Vec<String>,
+    positions: Vec<f64>,
+}
+
+impl<Ctx> HandleInsert<Colormap, NewColormap, Pg, Ctx> for colormaps::table
+where
+    colormaps::table: HandleInsert<Colormap, ValidColormap, Pg, Ctx>,
+{
+    fn handle_insert(
+        selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
+        executor: &Executor<'_, Ctx, WundergraphScalarValue>,
+        insertable: NewColormap,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let valid = insertable.validate().map_err(|e| e.to_field_error(None))?;
+        <Self as HandleInsert<Colormap, ValidColormap, Pg, Ctx>>::handle_insert(
+            selection, executor, valid,
+        )
+    }
+}
+
+impl<Ctx> HandleBatchInsert<Colormap, NewColormap, Pg, Ctx> for colormaps::table
+where
+    colormaps::table: HandleBatchInsert<Colormap, ValidColormap, Pg, Ctx>,
+{
+    fn handle_batch_insert(
+        selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
+        executor: &Executor<'_, Ctx, WundergraphScalarValue>,
+        batch: Vec<NewColormap>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let valid = batch
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| c.validate().map_err(|e| e.to_field_error(Some(i))))
+            .collect::<Result<Vec<_>, _>>()?;
+        <Self as HandleBatchInsert<Colormap, ValidColormap, Pg, Ctx>>::handle_batch_insert(
+            selection, executor, valid,
+        )
+    }
+}
+
+impl<Ctx> HandleUpdate<Colormap, ColormapChangeset, Pg, Ctx> for colormaps::table
+where
+    colormaps::table: HandleUpdate<Colormap, ValidColormapChangeset, Pg, Ctx>,
+{
+    fn handle_update(
+        selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
+        executor: &Executor<'_, Ctx, WundergraphScalarValue>,
+        update: &ColormapChangeset,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let valid = update.validate().map_err(|e| e.to_field_error(None))?;
+        <Self as HandleUpdate<Colormap, ValidColormapChangeset, Pg, Ctx>>::handle_update(
+            selection, executor, &valid,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(colors: &[&str], positions: &[f64]) -> Result<(), Vec<String>> {
+        let colors = colors.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        validate(&colors, positions).map_err(|e| e.0.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex_color("#abc"), Some([0xaa, 0xbb, 0xcc, 0xff]));
+        assert_eq!(parse_hex_color("#abcd"), Some([0xaa, 0xbb, 0xcc, 0xdd]));
+        assert_eq!(parse_hex_color("#A0b1C2"), Some([0xa0, 0xb1, 0xc2, 0xff]));
+        assert_eq!(parse_hex_color("#a0b1c280"), Some([0xa0, 0xb1, 0xc2, 0x80]));
+        assert_eq!(parse_hex_color("#GGGGGG"), None);
+        assert_eq!(parse_hex_color("a0b1c2"), None);
+        assert_eq!(parse_hex_color("#abcde"), None);
+        assert_eq!(parse_hex_color("#"), None);
+        assert_eq!(parse_hex_color("#ééé"), None);
+    }
+
+    #[test]
+    fn valid_colormaps() {
+        assert_eq!(check(&["#000"], &[0.0]), Ok(()));
+        assert_eq!(
+            check(&["#000", "#abc", "#ffffff"], &[0.0, 0.5, 1.0]),
+            Ok(())
+        );
+        assert_eq!(check(&["#000", "#fff"], &[0.5, 0.5]), Ok(()));
+    }
+
+    #[test]
+    fn length_mismatch() {
+        assert_eq!(
+            check(&["#000", "#fff"], &[0.0]),
+            Err(vec![String::from(
+                "positions: expected 2 positions, one per color, got 1"
+            )])
+        );
+        assert_eq!(
+            check(&[], &[]),
+            Err(vec![String::from("colors: at least one color is required")])
+        );
+    }
+
+    #[test]
+    fn bad_colors() {
+        assert_eq!(
+            check(&["#GGGGGG", "000000", "#abc"], &[0.0, 0.5, 1.0]),
+            Err(vec![
+                String::from("colors[0]: \"#GGGGGG\" is not a CSS hex color"),
+                String::from("colors[1]: \"000000\" is not a CSS hex color"),
+            ])
+        );
+    }
+
+    #[test]
+    fn bad_positions() {
+        assert_eq!(
+            check(&["#000", "#111", "#222"], &[-0.5, 0.2, 1.5]),
+            Err(vec![
+                String::from("positions[0]: -0.5 is not within [0, 1]"),
+                String::from("positions[2]: 1.5 is not within [0, 1]"),
+            ])
+        );
+        assert_eq!(
+            check(&["#000", "#111", "#222"], &[0.0, 0.8, 0.4]),
+            Err(vec![String::from(
+                "positions[2]: 0.4 is lower than the previous position 0.8"
+            )])
+        );
+        assert!(check(&["#000"], &[f64::NAN]).is_err());
+    }
+
+    #[test]
+    fn field_error_extensions() {
+        let error = ColormapError(vec![
+            Violation {
+                field: "colors",
+                index: Some(1),
+                message: "bad".into(),
+            },
+            Violation {
+                field: "positions",
+                index: None,
+                message: "short".into(),
+            },
+        ]);
+        let field_error = error.to_field_error(None);
+        assert_eq!(
+            field_error.message(),
+            "Invalid colormap: colors[1]: bad; positions: short"
+        );
+        let extensions = serde_json::to_value(field_error.extensions()).unwrap();
+        assert_eq!(
+            extensions,
+            serde_json::json!({
+                "code": "INVALID_COLORMAP",
+                "field": "colors",
+                "index": 1,
+                "violations": [
+                    { "field": "colors", "index": 1, "message": "bad" },
+                    { "field": "positions", "index": null, "message": "short" },
+                ],
+            })
+        );
+
+        let field_error = error.to_field_error(Some(3));
+        assert!(field_error
+            .message()
+            .starts_with("Colormap 3: Invalid colormap"));
+        let extensions = serde_json::to_value(field_error.extensions()).unwrap();
+        assert_eq!(extensions["item"], 3);
+    }
+}
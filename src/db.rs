@@ -0,0 +1,234 @@
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::r2d2::event::{CheckoutEvent, TimeoutEvent};
+use diesel::r2d2::{ConnectionManager, HandleEvent, Pool, PoolError};
+use diesel::sql_types::Bool;
+use diesel::{select, RunQueryDsl};
+use diesel_migrations::{setup_database, MigrationConnection, RunMigrationsError};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+/// Settings of the database connection pool
+///
+/// Timeouts are given in seconds, a lifetime or idle timeout of 0
+/// disables it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: u64,
+    pub max_lifetime: u64,
+    pub idle_timeout: u64,
+    /// Checkouts waiting longer than this many milliseconds are reported
+    /// as pool saturation
+    pub slow_checkout_ms: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: 30,
+            max_lifetime: 30 * 60,
+            idle_timeout: 10 * 60,
+            slow_checkout_ms: 100,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_size == 0 {
+            return Err(String::from(
+                "database.pool.max_size must be greater than 0",
+            ));
+        }
+        if matches!(self.min_idle, Some(min_idle) if min_idle > self.max_size) {
+            return Err(String::from(
+                "database.pool.min_idle must not be greater than database.pool.max_size",
+            ));
+        }
+        if self.connection_timeout == 0 {
+            return Err(String::from(
+                "database.pool.connection_timeout must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn optional_secs(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+pub fn build_pool(db_url: &str, config: &PoolConfig) -> Result<DbPool, PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    Pool::builder()
+        .max_size(config.max_size)
+        .min_idle(config.min_idle)
+        .connection_timeout(Duration::from_secs(config.connection_timeout))
+        .max_lifetime(optional_secs(config.max_lifetime))
+        .idle_timeout(optional_secs(config.idle_timeout))
+        .event_handler(Box::new(PoolMonitor {
+            max_size: config.max_size,
+            slow_checkout: Duration::from_millis(config.slow_checkout_ms),
+        }))
+        .build(manager)
+}
+
+/// Logs checkouts that had to wait for a connection to be released
+#[derive(Debug)]
+struct PoolMonitor {
+    max_size: u32,
+    slow_checkout: Duration,
+}
+
+impl HandleEvent for PoolMonitor {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        if event.duration() >= self.slow_checkout {
+            warn!(
+                "Waited {:?} for a database connection, the pool ({} connections) is saturated",
+                event.duration(),
+                self.max_size
+            );
+        }
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        warn!(
+            "Timed out after {:?} waiting for a database connection, the pool ({} connections) is exhausted",
+            event.timeout(),
+            self.max_size
+        );
+    }
+}
+
+diesel_migrations::embed_migrations!("migrations");
+
+// `MIGRATION_VERSIONS`, the sorted versions of the embedded migrations
+include!(concat!(env!("OUT_DIR"), "/migration_versions.rs"));
+
+/// What the server does with the embedded migrations on boot
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    /// Apply every pending migration
+    #[default]
+    Run,
+    /// Refuse to start if a migration is pending
+    Check,
+    /// Leave the database schema alone
+    #[serde(alias = "off")]
+    Skip,
+}
+
+impl FromStr for MigrationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "run" => Ok(MigrationMode::Run),
+            "check" => Ok(MigrationMode::Check),
+            "skip" | "off" => Ok(MigrationMode::Skip),
+            _ => Err(format!(
+                "unknown migration mode `{}`, expected run, check or skip",
+                s
+            )),
+        }
+    }
+}
+
+/// Apply pending embedded migrations and return the versions that were run
+pub fn run_migrations(conn: &PgConnection) -> Result<Vec<String>, RunMigrationsError> {
+    setup_database(conn)?;
+    let before = conn.previously_run_migration_versions()?;
+    embedded_migrations::run(conn)?;
+    let mut applied = conn
+        .previously_run_migration_versions()?
+        .difference(&before)
+        .cloned()
+        .collect::<Vec<_>>();
+    applied.sort();
+    Ok(applied)
+}
+
+/// Return the versions of the embedded migrations not applied yet
+///
+/// Only reads the table diesel records applied migrations in, nothing is
+/// executed or created so it is cheap enough for health checks.
+pub fn pending_migrations(conn: &PgConnection) -> Result<Vec<String>, RunMigrationsError> {
+    let tracked = select(sql::<Bool>(
+        "to_regclass('__diesel_schema_migrations') IS NOT NULL",
+    ))
+    .get_result::<bool>(conn)?;
+    let applied = if tracked {
+        conn.previously_run_migration_versions()?
+    } else {
+        HashSet::new()
+    };
+    Ok(not_applied(MIGRATION_VERSIONS, &applied))
+}
+
+fn not_applied(embedded: &[&str], applied: &HashSet<String>) -> Vec<String> {
+    embedded
+        .iter()
+        .filter(|version| !applied.contains(**version))
+        .map(|version| (*version).to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_versions() {
+        assert_eq!(MIGRATION_VERSIONS.first(), Some(&"00000000000000"));
+        assert!(MIGRATION_VERSIONS.contains(&"20261018140000"));
+        assert!(MIGRATION_VERSIONS.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn pending() {
+        let embedded = &["00000000000000", "20261018110000", "20261018120000"];
+        let applied = ["00000000000000", "20261018120000", "20200101000000"]
+            .iter()
+            .map(|v| String::from(*v))
+            .collect::<HashSet<_>>();
+        assert_eq!(not_applied(embedded, &applied), vec!["20261018110000"]);
+        assert_eq!(not_applied(embedded, &HashSet::new()).len(), 3);
+    }
+
+    #[test]
+    fn pool_validation() {
+        let mut config = PoolConfig::default();
+        assert!(config.validate().is_ok());
+        config.min_idle = Some(config.max_size);
+        assert!(config.validate().is_ok());
+        config.min_idle = Some(config.max_size + 1);
+        assert!(config.validate().is_err());
+        config.min_idle = None;
+        config.max_size = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn migration_modes() {
+        assert_eq!(MigrationMode::default(), MigrationMode::Run);
+        assert_eq!("Skip".parse(), Ok(MigrationMode::Skip));
+        assert_eq!("off".parse(), Ok(MigrationMode::Skip));
+        assert_eq!("check".parse(), Ok(MigrationMode::Check));
+        assert!("apply".parse::<MigrationMode>().is_err());
+    }
+}
@@ -0,0 +1,838 @@
+use crate::frame_time::{FrameTime, FrameTimePredicate, TimeCondition};
+use crate::generated::*;
+use crate::timeline::{self, Bucket, TimelineQuery, Track, DEFAULT_BUCKETS};
+use diesel::associations::HasTable;
+use diesel::pg::Pg;
+use juniper::parser::SourcePosition;
+use juniper::{
+    meta, Executor, LookAheadMethods, LookAheadSelection, LookAheadValue, Registry, Selection,
+    Value,
+};
+use std::marker::PhantomData;
+use wundergraph::error::{Result, WundergraphError};
+use wundergraph::graphql_type::WundergraphGraphqlMapper;
+use wundergraph::juniper_ext::FromLookAheadValue;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Frames of a movie within `[from, to)`, in time order
+///
+/// Loads the same rows and GraphQL type as the frame entity `F`, used as
+/// the target of a `HasMany` to give the field its `from` and `to`
+/// arguments: `imagesBetween: HasMany<FramesBetween<Image>, images::color_movie_id>`.
+#[derive(Debug, Clone)]
+pub struct FramesBetween<F>(PhantomData<F>);
+
+/// The frame of a movie picked by `mode` at `time`, like
+/// [`FramesBetween`](struct.FramesBetween.html) but with `time` and `mode`
+/// arguments
+///
+/// The field is a list holding at most one frame, lookups follow the
+/// `atOrBefore`, `atOrAfter` and `nearest` filters of `FrameTime`.
+#[derive(Debug, Clone)]
+pub struct FrameAt<F>(PhantomData<F>);
+
+/// Timeline of a movie, its frames counted in buckets of equal duration
+/// with the first frame of each, see [`timeline`](../timeline/fn.timeline.html)
+///
+/// Registered with `from`, `to` and `buckets` arguments and
+/// [`TimelineBucket`](struct.TimelineBucket.html) as GraphQL type.
+#[derive(Debug, Clone)]
+pub struct FrameTimeline<F>(PhantomData<F>);
+
+/// A bucket of a [`FrameTimeline`](struct.FrameTimeline.html), `frame` is
+/// resolved beforehand like the frame entity `F`
+#[derive(Debug)]
+pub struct TimelineBucket<F, Ctx> {
+    start: f64,
+    end: f64,
+    count: i64,
+    frame: Value<WundergraphScalarValue>,
+    entity: PhantomData<(F, Ctx)>,
+}
+
+impl<F, Ctx> TimelineBucket<F, Ctx> {
+    fn new(bucket: Bucket, frame: Value<WundergraphScalarValue>) -> Self {
+        TimelineBucket {
+            start: bucket.start,
+            end: bucket.end,
+            count: bucket.count,
+            frame,
+            entity: PhantomData,
+        }
+    }
+}
+
+impl<F: HasTable> HasTable for FramesBetween<F> {
+    type Table = F::Table;
+
+    fn table() -> Self::Table {
+        F::table()
+    }
+}
+
+impl<F: HasTable> HasTable for FrameAt<F> {
+    type Table = F::Table;
+
+    fn table() -> Self::Table {
+        F::table()
+    }
+}
+
+impl<F: HasTable> HasTable for FrameTimeline<F> {
+    type Table = F::Table;
+
+    fn table() -> Self::Table {
+        F::table()
+    }
+}
+
+/// Which frame `imageAt` and `vectorDataAt` pick
+#[derive(Debug, Copy, Clone, PartialEq, GraphQLEnum)]
+pub enum FrameAtMode {
+    /// The last frame at or before the time
+    BEFORE,
+    /// The first frame at or after the time
+    AFTER,
+    /// The frame before, or the one after when it is strictly closer
+    NEAREST,
+}
+
+impl FrameAtMode {
+    fn condition(self) -> TimeCondition {
+        match self {
+            FrameAtMode::BEFORE => TimeCondition::AtOrBefore,
+            FrameAtMode::AFTER => TimeCondition::AtOrAfter,
+            FrameAtMode::NEAREST => TimeCondition::Nearest,
+        }
+    }
+}
+
+impl FromLookAheadValue for FrameAtMode {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        let name = match *v {
+            LookAheadValue::Enum(name) => name,
+            LookAheadValue::Scalar(WundergraphScalarValue::String(ref name)) => name,
+            _ => return None,
+        };
+        match name {
+            "BEFORE" => Some(FrameAtMode::BEFORE),
+            "AFTER" => Some(FrameAtMode::AFTER),
+            "NEAREST" => Some(FrameAtMode::NEAREST),
+            _ => None,
+        }
+    }
+}
+
+/// `filter` of the frame entity `F`, `from` and `to`
+fn register_between<'r, F, Ctx>(
+    registry: &mut Registry<'r, WundergraphScalarValue>,
+    field: meta::Field<'r, WundergraphScalarValue>,
+) -> meta::Field<'r, WundergraphScalarValue>
+where
+    F: WundergraphGraphqlMapper<Pg, Ctx>,
+{
+    let field = F::register_arguments(registry, field);
+    let from = registry
+        .arg::<FrameTime>("from", &())
+        .description("Start of the range, included");
+    let to = registry
+        .arg::<FrameTime>("to", &())
+        .description("End of the range, excluded");
+    field.argument(from).argument(to)
+}
+
+fn register_at<'r>(
+    registry: &mut Registry<'r, WundergraphScalarValue>,
+    field: meta::Field<'r, WundergraphScalarValue>,
+) -> meta::Field<'r, WundergraphScalarValue> {
+    let time = registry.arg::<FrameTime>("time", &());
+    let mode = registry
+        .arg_with_default::<FrameAtMode>("mode", &FrameAtMode::NEAREST, &())
+        .description("Which frame to pick around the time");
+    field.argument(time).argument(mode)
+}
+
+fn register_timeline<'r>(
+    registry: &mut Registry<'r, WundergraphScalarValue>,
+    field: meta::Field<'r, WundergraphScalarValue>,
+) -> meta::Field<'r, WundergraphScalarValue> {
+    let from = registry
+        .arg::<Option<FrameTime>>("from", &())
+        .description("Start of the timeline, the first frame by default");
+    let to = registry
+        .arg::<Option<FrameTime>>("to", &())
+        .description("End of the timeline, included, the last frame by default");
+    let buckets = registry
+        .arg_with_default::<i32>("buckets", &DEFAULT_BUCKETS, &())
+        .description("Number of buckets");
+    field.argument(from).argument(to).argument(buckets)
+}
+
+/// Executor and selection set of the field `name` in `selection`, like
+/// wundergraph gives to nested fields
+fn sub_executor<'a, Ctx>(
+    executor: &'a Executor<'a, Ctx, WundergraphScalarValue>,
+    name: &'a str,
+    selection: Option<&'a [Selection<'a, WundergraphScalarValue>]>,
+) -> (
+    Executor<'a, Ctx, WundergraphScalarValue>,
+    Option<&'a [Selection<'a, WundergraphScalarValue>]>,
+) {
+    let (alias, position, selection) = selection
+        .and_then(|selection| {
+            selection.iter().find_map(|s| match s {
+                Selection::Field(field) if field.item.name.item == name => Some((
+                    field.item.alias.unwrap_or(field.item.name).item,
+                    field.start,
+                    field.item.selection_set.as_deref(),
+                )),
+                _ => None,
+            })
+        })
+        .unwrap_or((name, SourcePosition::new(0, 0, 0), None));
+    (
+        executor.field_sub_executor(alias, name, position, selection),
+        selection,
+    )
+}
+
+fn argument<T: FromLookAheadValue>(
+    look_ahead: &LookAheadSelection<'_, WundergraphScalarValue>,
+    name: &str,
+) -> Option<T> {
+    look_ahead
+        .argument(name)
+        .and_then(|arg| T::from_look_ahead(arg.value()))
+}
+
+fn time_argument(
+    look_ahead: &LookAheadSelection<'_, WundergraphScalarValue>,
+    name: &str,
+) -> Result<FrameTime> {
+    argument(look_ahead, name).ok_or(WundergraphError::CouldNotBuildFilterArgument)
+}
+
+/// Implement loading `FramesBetween`, `FrameAt` and `FrameTimeline` of a
+/// frame entity as associations of its movie
+///
+/// The lookups load like the entity, `FramesBetween` keeps its `filter`
+/// argument. Timelines of all the movies are queried together, then the
+/// first frames of their buckets are loaded in one query.
+macro_rules! frame_lookups {
+    ($(
+        $frame:ident of $parent:ident on $table:ident($time:ident, $series:ident, $key:ident)
+        in $track:expr => $bucket:expr,
+    )+) => {
+        $(
+            frame_lookups!(
+                @loading FramesBetween<$frame>,
+                wundergraph::graphql_type::GraphqlWrapper<$frame, Pg, Ctx>,
+                register_between::<$frame, Ctx>
+            );
+            frame_lookups!(
+                @loading FrameAt<$frame>,
+                wundergraph::graphql_type::GraphqlWrapper<$frame, Pg, Ctx>,
+                register_at
+            );
+            frame_lookups!(@loading FrameTimeline<$frame>, TimelineBucket<$frame, Ctx>, register_timeline);
+
+            impl<Ctx> juniper::GraphQLType<WundergraphScalarValue> for TimelineBucket<$frame, Ctx>
+            where
+                Ctx: wundergraph::WundergraphContext + 'static,
+                <Ctx as wundergraph::WundergraphContext>::Connection:
+                    diesel::Connection<Backend = Pg>,
+            {
+                type Context = Ctx;
+                type TypeInfo = ();
+
+                fn name(_info: &()) -> Option<&str> {
+                    Some($bucket)
+                }
+
+                fn meta<'r>(
+                    info: &(),
+                    registry: &mut Registry<'r, WundergraphScalarValue>,
+                ) -> meta::MetaType<'r, WundergraphScalarValue>
+                where
+                    WundergraphScalarValue: 'r,
+                {
+                    let fields = [
+                        registry
+                            .field::<f64>("start", &())
+                            .description("Start of the bucket in seconds since the epoch, included"),
+                        registry
+                            .field::<f64>("end", &())
+                            .description("End of the bucket in seconds since the epoch"),
+                        registry
+                            .field::<i64>("count", &())
+                            .description("Number of frames in the bucket"),
+                        registry
+                            .field::<Option<wundergraph::graphql_type::GraphqlWrapper<$frame, Pg, Ctx>>>(
+                                "frame",
+                                &(),
+                            )
+                            .description("First frame of the bucket"),
+                    ];
+                    registry.build_object_type::<Self>(info, &fields).into_meta()
+                }
+
+                fn resolve_field(
+                    &self,
+                    _info: &(),
+                    field_name: &str,
+                    _arguments: &juniper::Arguments<WundergraphScalarValue>,
+                    executor: &juniper::Executor<Ctx, WundergraphScalarValue>,
+                ) -> juniper::ExecutionResult<WundergraphScalarValue> {
+                    match field_name {
+                        "start" => executor.resolve_with_ctx(&(), &self.start),
+                        "end" => executor.resolve_with_ctx(&(), &self.end),
+                        "count" => executor.resolve_with_ctx(&(), &self.count),
+                        "frame" => Ok(self.frame.clone()),
+                        _ => unreachable!("Unknown field {} of {}", field_name, $bucket),
+                    }
+                }
+            }
+
+            impl<Ctx> wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                $parent::table,
+                Pg,
+                Ctx,
+                $table::$series,
+            > for FrameTimeline<$frame>
+            where
+                Ctx: wundergraph::WundergraphContext + 'static,
+                <Ctx as wundergraph::WundergraphContext>::Connection:
+                    diesel::Connection<Backend = Pg>,
+            {
+                type Key = i32;
+
+                fn resolve(
+                    global_args: &[juniper::LookAheadArgument<WundergraphScalarValue>],
+                    look_ahead: &juniper::LookAheadSelection<'_, WundergraphScalarValue>,
+                    selection: Option<&'_ [juniper::Selection<'_, WundergraphScalarValue>]>,
+                    keys: &[Option<i32>],
+                    executor: &juniper::Executor<'_, Ctx, WundergraphScalarValue>,
+                ) -> Result<
+                    std::collections::HashMap<
+                        Option<i32>,
+                        Vec<juniper::Value<WundergraphScalarValue>>,
+                    >,
+                > {
+                    use diesel::{ExpressionMethods, NullableExpressionMethods, QueryDsl, RunQueryDsl};
+                    use std::collections::HashMap;
+                    use wundergraph::query_builder::selection::fields::WundergraphBelongsTo;
+                    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler};
+
+                    let query = TimelineQuery {
+                        from: argument::<FrameTime>(look_ahead, "from").map(|t| t.seconds()),
+                        to: argument::<FrameTime>(look_ahead, "to").map(|t| t.seconds()),
+                        buckets: argument(look_ahead, "buckets"),
+                    };
+                    let conn = executor.context().get_connection();
+                    let ids = keys.iter().filter_map(|k| *k).collect::<Vec<_>>();
+                    let timelines = match timeline::movie_timelines(conn, $track, &ids, &query) {
+                        Ok(timelines) => timelines,
+                        // Reported with its code, the field resolves to empty lists
+                        Err(e) => {
+                            executor.push_error(e.into_field_error());
+                            return Ok(HashMap::new());
+                        }
+                    };
+                    // A movie whose timeline failed gets an empty list, the
+                    // error names it
+                    let timelines = timelines
+                        .into_iter()
+                        .filter_map(|(id, timeline)| match timeline {
+                            Ok(timeline) => Some((id, timeline)),
+                            Err(e) => {
+                                executor.push_error(e.into_movie_field_error(id));
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>();
+
+                    let frames = match look_ahead.select_child("frame") {
+                        Some(frame) => {
+                            let ids = timelines
+                                .iter()
+                                .flat_map(|(_, t)| t.buckets.iter())
+                                .filter_map(|b| b.frame.as_ref().map(|f| f.id))
+                                .collect::<Vec<_>>();
+                            let query = <BoxedQuery<$frame, Pg, Ctx> as QueryDsl>::select(
+                                <$frame as LoadingHandler<Pg, Ctx>>::build_query(global_args, frame)?,
+                                (
+                                    $table::$key.nullable(),
+                                    <$frame as LoadingHandler<Pg, Ctx>>::get_select(frame)?,
+                                ),
+                            );
+                            let query = <_ as QueryDsl>::filter(query, $table::$key.eq_any(ids));
+                            let (executor, selection) = sub_executor(executor, "frame", selection);
+                            // Keyed by frame
+                            <$frame as WundergraphBelongsTo<
+                                $parent::table,
+                                Pg,
+                                Ctx,
+                                $table::$series,
+                            >>::build_response(
+                                query.load(conn)?,
+                                global_args,
+                                frame,
+                                selection,
+                                &executor,
+                            )?
+                        }
+                        None => HashMap::new(),
+                    };
+
+                    Ok(timelines
+                        .into_iter()
+                        .map(|(id, t)| {
+                            let buckets = t
+                                .buckets
+                                .into_iter()
+                                .map(|bucket| {
+                                    let frame = bucket
+                                        .frame
+                                        .as_ref()
+                                        .and_then(|f| frames.get(&Some(f.id)))
+                                        .and_then(|f| f.first().cloned())
+                                        .unwrap_or_else(Value::null);
+                                    executor.resolve_into_value(
+                                        &(),
+                                        &TimelineBucket::<$frame, Ctx>::new(bucket, frame),
+                                    )
+                                })
+                                .collect();
+                            (Some(id), buckets)
+                        })
+                        .collect())
+                }
+            }
+
+            impl<Ctx> wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                $parent::table,
+                Pg,
+                Ctx,
+                $table::$series,
+            > for FramesBetween<$frame>
+            where
+                Ctx: wundergraph::WundergraphContext + 'static,
+                <Ctx as wundergraph::WundergraphContext>::Connection:
+                    diesel::Connection<Backend = Pg>,
+            {
+                type Key = i32;
+
+                fn resolve(
+                    global_args: &[juniper::LookAheadArgument<WundergraphScalarValue>],
+                    look_ahead: &juniper::LookAheadSelection<'_, WundergraphScalarValue>,
+                    selection: Option<&'_ [juniper::Selection<'_, WundergraphScalarValue>]>,
+                    keys: &[Option<i32>],
+                    executor: &juniper::Executor<'_, Ctx, WundergraphScalarValue>,
+                ) -> Result<
+                    std::collections::HashMap<
+                        Option<i32>,
+                        Vec<juniper::Value<WundergraphScalarValue>>,
+                    >,
+                > {
+                    use diesel::{ExpressionMethods, NullableExpressionMethods, QueryDsl, RunQueryDsl};
+                    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler};
+
+                    let from = time_argument(look_ahead, "from")?;
+                    let to = time_argument(look_ahead, "to")?;
+                    let conn = executor.context().get_connection();
+                    let query = <BoxedQuery<Self, Pg, Ctx> as QueryDsl>::select(
+                        <Self as LoadingHandler<Pg, Ctx>>::build_query(global_args, look_ahead)?,
+                        (
+                            $table::$series.nullable(),
+                            <Self as LoadingHandler<Pg, Ctx>>::get_select(look_ahead)?,
+                        ),
+                    );
+                    let query = <_ as QueryDsl>::filter(query, $table::$series.nullable().eq_any(keys));
+                    let query = <_ as QueryDsl>::filter(query, $table::$time.ge(from));
+                    let query = <_ as QueryDsl>::filter(query, $table::$time.lt(to));
+                    let query = <_ as QueryDsl>::order_by(query, ($table::$time, $table::$key));
+                    <Self as wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                        $parent::table,
+                        Pg,
+                        Ctx,
+                        $table::$series,
+                    >>::build_response(query.load(conn)?, global_args, look_ahead, selection, executor)
+                }
+            }
+
+            impl<Ctx> wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                $parent::table,
+                Pg,
+                Ctx,
+                $table::$series,
+            > for FrameAt<$frame>
+            where
+                Ctx: wundergraph::WundergraphContext + 'static,
+                <Ctx as wundergraph::WundergraphContext>::Connection:
+                    diesel::Connection<Backend = Pg>,
+            {
+                type Key = i32;
+
+                fn resolve(
+                    global_args: &[juniper::LookAheadArgument<WundergraphScalarValue>],
+                    look_ahead: &juniper::LookAheadSelection<'_, WundergraphScalarValue>,
+                    selection: Option<&'_ [juniper::Selection<'_, WundergraphScalarValue>]>,
+                    keys: &[Option<i32>],
+                    executor: &juniper::Executor<'_, Ctx, WundergraphScalarValue>,
+                ) -> Result<
+                    std::collections::HashMap<
+                        Option<i32>,
+                        Vec<juniper::Value<WundergraphScalarValue>>,
+                    >,
+                > {
+                    use diesel::{NullableExpressionMethods, QueryDsl, RunQueryDsl};
+                    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler};
+
+                    let time = time_argument(look_ahead, "time")?;
+                    let mode = match look_ahead.argument("mode") {
+                        Some(_) => argument::<FrameAtMode>(look_ahead, "mode")
+                            .ok_or(WundergraphError::CouldNotBuildFilterArgument)?,
+                        None => FrameAtMode::NEAREST,
+                    };
+                    let movies = keys.iter().filter_map(|k| *k).collect();
+                    let conn = executor.context().get_connection();
+                    let query = <BoxedQuery<Self, Pg, Ctx> as QueryDsl>::select(
+                        <Self as LoadingHandler<Pg, Ctx>>::build_query(global_args, look_ahead)?,
+                        (
+                            $table::$series.nullable(),
+                            <Self as LoadingHandler<Pg, Ctx>>::get_select(look_ahead)?,
+                        ),
+                    );
+                    let query = <_ as QueryDsl>::filter(
+                        query,
+                        FrameTimePredicate::<$table::$time>::lookup(mode.condition(), time, movies),
+                    );
+                    <Self as wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                        $parent::table,
+                        Pg,
+                        Ctx,
+                        $table::$series,
+                    >>::build_response(query.load(conn)?, global_args, look_ahead, selection, executor)
+                }
+            }
+        )+
+    };
+    (@loading $lookup:ident<$frame:ident>, $graphql:ty, $register:expr) => {
+        impl<Ctx> WundergraphGraphqlMapper<Pg, Ctx> for $lookup<$frame>
+        where
+            Ctx: wundergraph::WundergraphContext + 'static,
+            <Ctx as wundergraph::WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+        {
+            type GraphQLType = $graphql;
+
+            fn register_arguments<'r>(
+                registry: &mut Registry<'r, WundergraphScalarValue>,
+                field: meta::Field<'r, WundergraphScalarValue>,
+            ) -> meta::Field<'r, WundergraphScalarValue> {
+                $register(registry, field)
+            }
+        }
+
+        impl<Ctx> wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx> for $lookup<$frame>
+        where
+            Ctx: wundergraph::WundergraphContext + 'static,
+            <Ctx as wundergraph::WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+        {
+            type Columns = <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::Columns;
+            type FieldList = <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::FieldList;
+            type PrimaryKeyIndex = <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::PrimaryKeyIndex;
+            type Filter = <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::Filter;
+
+            const FIELD_NAMES: &'static [&'static str] =
+                <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::FIELD_NAMES;
+            const TYPE_NAME: &'static str =
+                <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::TYPE_NAME;
+            const TYPE_DESCRIPTION: Option<&'static str> =
+                <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::TYPE_DESCRIPTION;
+
+            fn field_description(idx: usize) -> Option<&'static str> {
+                <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::field_description(idx)
+            }
+
+            fn field_deprecation(idx: usize) -> Option<Option<&'static str>> {
+                <$frame as wundergraph::query_builder::selection::LoadingHandler<Pg, Ctx>>::field_deprecation(idx)
+            }
+        }
+    };
+}
+
+frame_lookups! {
+    Image of color_movies on images(time, color_movie_id, id)
+        in Track::Images => "ImageTimelineBucket",
+    VectorData of vector_movies on vector_data(time, vector_movie_id, id)
+        in Track::VectorData => "VectorDataTimelineBucket",
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorFormat;
+    use diesel::{Connection, ExpressionMethods, PgConnection, RunQueryDsl};
+    use juniper::Variables;
+    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler, QueryModifier};
+    use wundergraph::WundergraphContext;
+
+    struct Context(PgConnection);
+
+    impl WundergraphContext for Context {
+        type Connection = PgConnection;
+
+        fn get_connection(&self) -> &PgConnection {
+            &self.0
+        }
+    }
+
+    impl<T> QueryModifier<T, Pg> for Context
+    where
+        T: LoadingHandler<Pg, Self>,
+    {
+        fn modify_query<'a>(
+            &self,
+            _select: &LookAheadSelection<'_, WundergraphScalarValue>,
+            query: BoxedQuery<'a, T, Pg, Self>,
+        ) -> Result<BoxedQuery<'a, T, Pg, Self>> {
+            Ok(query)
+        }
+    }
+
+    fn at(seconds: f64) -> FrameTime {
+        FrameTime::from_seconds(seconds).unwrap()
+    }
+
+    /// Ids of the frames in each list selected by `query`, in order
+    fn frames(ctx: &Context, query: &str, entity: &str, fields: &[&str]) -> Vec<Vec<i32>> {
+        let schema =
+            juniper::RootNode::new(Query::<Context>::default(), Mutation::<Context>::default());
+        let (result, errors) =
+            juniper::execute(query, None, &schema, &Variables::new(), ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let result = serde_json::to_value(&result).unwrap();
+        fields
+            .iter()
+            .map(|field| {
+                result[entity][field]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|frame| frame["id"].as_i64().unwrap() as i32)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Run with `TEST_DATABASE_URL` pointing to a migrated database, changes
+    /// are rolled back
+    #[test]
+    #[ignore]
+    fn frame_lookups() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let ctx = Context(PgConnection::establish(&url).unwrap());
+        let conn = &ctx.0;
+        conn.begin_test_transaction().unwrap();
+
+        let colormap: i32 = diesel::insert_into(colormaps::table)
+            .values((
+                colormaps::name.eq("lookup-gray"),
+                colormaps::colors.eq(vec!["#000000", "#ffffff"]),
+                colormaps::positions.eq(vec![0.0, 1.0]),
+            ))
+            .returning(colormaps::id)
+            .get_result(conn)
+            .unwrap();
+        let movie: i32 = diesel::insert_into(color_movies::table)
+            .values((
+                color_movies::format.eq(ColorFormat::GRAY8),
+                color_movies::default_colormap.eq(colormap),
+            ))
+            .returning(color_movies::id)
+            .get_result(conn)
+            .unwrap();
+        // Two images at 10, inserted out of time order
+        let times = [20.0, 10.0, 0.0, 10.0, 30.0];
+        let images: Vec<i32> = diesel::insert_into(images::table)
+            .values(
+                times
+                    .iter()
+                    .map(|t| {
+                        (
+                            images::time.eq(at(*t)),
+                            images::path.eq("lookup.png"),
+                            images::color_movie_id.eq(movie),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .returning(images::id)
+            .get_results(conn)
+            .unwrap();
+        let (at20, first10, at0, second10, at30) =
+            (images[0], images[1], images[2], images[3], images[4]);
+
+        let color = |fields: &str| {
+            format!(
+                "{{ ColorMovie(primaryKey: {{id: {}}}) {{ {} }} }}",
+                movie, fields
+            )
+        };
+        assert_eq!(
+            frames(
+                &ctx,
+                &color("imagesBetween(from: 10, to: 30) { id } images { id }"),
+                "ColorMovie",
+                &["imagesBetween"],
+            ),
+            vec![vec![first10, second10, at20]]
+        );
+        assert_eq!(
+            frames(
+                &ctx,
+                &color(
+                    r#"imagesBetween(from: "1970-01-01T00:00:00Z", to: 10.5, filter: {path: {eq: "lookup.png"}}) { id }"#
+                ),
+                "ColorMovie",
+                &["imagesBetween"],
+            ),
+            vec![vec![at0, first10, second10]]
+        );
+
+        let image_at = |args: &str| {
+            frames(
+                &ctx,
+                &color(&format!("imageAt({}) {{ id time }}", args)),
+                "ColorMovie",
+                &["imageAt"],
+            )
+            .remove(0)
+        };
+        // Of images sharing a time, the last one is before and the first one
+        // after, the one before wins ties
+        assert_eq!(image_at("time: 10, mode: BEFORE"), vec![second10]);
+        assert_eq!(image_at("time: 10, mode: AFTER"), vec![first10]);
+        assert_eq!(image_at("time: 10"), vec![second10]);
+        assert_eq!(image_at("time: 25, mode: NEAREST"), vec![at20]);
+        assert_eq!(image_at("time: 26"), vec![at30]);
+        assert_eq!(image_at("time: -1, mode: BEFORE"), Vec::<i32>::new());
+        assert_eq!(image_at("time: 31, mode: AFTER"), Vec::<i32>::new());
+
+        let schema =
+            juniper::RootNode::new(Query::<Context>::default(), Mutation::<Context>::default());
+        let query = color("timeline(buckets: 3) { start end count frame { id path } }");
+        let (result, errors) =
+            juniper::execute(&query, None, &schema, &Variables::new(), &ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap()["ColorMovie"]["timeline"],
+            serde_json::json!([
+                { "start": 0.0, "end": 10.0, "count": 1, "frame": { "id": at0, "path": "lookup.png" } },
+                { "start": 10.0, "end": 20.0, "count": 2, "frame": { "id": first10, "path": "lookup.png" } },
+                { "start": 20.0, "end": 30.0, "count": 2, "frame": { "id": at20, "path": "lookup.png" } },
+            ])
+        );
+        let query = color("timeline(from: 30, to: 0) { count }");
+        let (result, errors) =
+            juniper::execute(&query, None, &schema, &Variables::new(), &ctx).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error().extensions(),
+            &graphql_value!({ "code": "INVALID_PARAMETER" })
+        );
+        assert_eq!(
+            serde_json::to_value(&result).unwrap()["ColorMovie"]["timeline"],
+            serde_json::json!([])
+        );
+
+        // Only the movie whose last frame is before `from` fails
+        let later: i32 = diesel::insert_into(color_movies::table)
+            .values((
+                color_movies::format.eq(ColorFormat::GRAY8),
+                color_movies::default_colormap.eq(colormap),
+            ))
+            .returning(color_movies::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(images::table)
+            .values((
+                images::time.eq(at(100.0)),
+                images::path.eq("lookup.png"),
+                images::color_movie_id.eq(later),
+            ))
+            .execute(conn)
+            .unwrap();
+        let query = format!(
+            "{{ ColorMovies(filter: {{id: {{eq_any: [{}, {}]}}}}, order: [{{column: id, direction: ASC}}]) \
+             {{ id timeline(from: 50, buckets: 2) {{ count }} }} }}",
+            movie, later
+        );
+        let (result, errors) =
+            juniper::execute(&query, None, &schema, &Variables::new(), &ctx).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error().extensions(),
+            &graphql_value!({ "code": "INVALID_PARAMETER", "id": (movie) })
+        );
+        assert_eq!(
+            serde_json::to_value(&result).unwrap()["ColorMovies"],
+            serde_json::json!([
+                { "id": movie, "timeline": [] },
+                { "id": later, "timeline": [{ "count": 0 }, { "count": 1 }] },
+            ])
+        );
+
+        // The filters pick the same frames
+        assert_eq!(
+            frames(
+                &ctx,
+                &color("images(filter: {time: {nearest: 10}}) { id }"),
+                "ColorMovie",
+                &["images"],
+            ),
+            vec![vec![second10]]
+        );
+
+        let vector_movie: i32 = diesel::insert_into(vector_movies::table)
+            .default_values()
+            .returning(vector_movies::id)
+            .get_result(conn)
+            .unwrap();
+        let data: Vec<i32> = diesel::insert_into(vector_data::table)
+            .values(
+                [5.0, 15.0]
+                    .iter()
+                    .map(|t| {
+                        (
+                            vector_data::time.eq(at(*t)),
+                            vector_data::vector_movie_id.eq(vector_movie),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .returning(vector_data::id)
+            .get_results(conn)
+            .unwrap();
+        assert_eq!(
+            frames(
+                &ctx,
+                &format!(
+                    "{{ VectorMovie(primaryKey: {{id: {}}}) {{ \
+                     vectorDataAt(time: 7, mode: AFTER) {{ id }} \
+                     vectorDataBetween(from: 0, to: 15) {{ id }} }} }}",
+                    vector_movie
+                ),
+                "VectorMovie",
+                &["vectorDataAt", "vectorDataBetween"],
+            ),
+            vec![vec![data[1]], vec![data[0]]]
+        );
+
+        // Times are checked when the query is validated
+        let schema =
+            juniper::RootNode::new(Query::<Context>::default(), Mutation::<Context>::default());
+        let query = color(r#"imageAt(time: "yesterday") { id }"#);
+        assert!(juniper::execute(&query, None, &schema, &Variables::new(), &ctx).is_err());
+    }
+}
@@ -0,0 +1,584 @@
+use crate::generated::{color_movies, images, vector_data, vector_movies, Image, VectorData};
+use chrono::{DateTime, NaiveDateTime};
+use diesel::associations::HasTable;
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::query_source::QuerySource;
+use diesel::serialize::{self, ToSql};
+use diesel::sql_types::{Array, Bool, Int4, Nullable, Timestamp};
+use diesel::{Column, QueryResult};
+use indexmap::IndexMap;
+use juniper::meta::Argument;
+use juniper::parser::{ParseError, ScalarToken, Token};
+use juniper::{
+    FromInputValue, InputValue, LookAheadValue, ParseScalarResult, ParseScalarValue, Registry,
+    ToInputValue, Value,
+};
+use std::io::Write;
+use std::marker::PhantomData;
+use wundergraph::juniper_ext::{FromLookAheadValue, NameBuilder, Nameable};
+use wundergraph::query_builder::selection::filter::{
+    AsColumnFilter, BuildFilter, FilterOption, FilterValue, InnerFilter,
+};
+use wundergraph::query_builder::types::{PlaceHolder, WundergraphValue};
+use wundergraph::scalar::WundergraphScalarValue;
+
+const NAIVE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Time of an `Image` or a `VectorData`, a `TIMESTAMP` taken as UTC
+///
+/// Replaces the `NaiveDateTime` scalar of the generated schema, still seconds
+/// since the epoch but with sub-second precision, and also accepts RFC 3339
+/// strings as input. Its filter adds range and nearest frame lookups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[sql_type = "Timestamp"]
+pub struct FrameTime(pub NaiveDateTime);
+
+impl FrameTime {
+    pub fn from_seconds(seconds: f64) -> Option<Self> {
+        if !seconds.is_finite() {
+            return None;
+        }
+        let whole = seconds.floor();
+        let nanos = ((seconds - whole) * 1e9).round().min(999_999_999.0) as u32;
+        NaiveDateTime::from_timestamp_opt(whole as i64, nanos).map(FrameTime)
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.0.timestamp() as f64 + f64::from(self.0.timestamp_subsec_nanos()) / 1e9
+    }
+
+    /// RFC 3339, converted to UTC, or without offset
+    pub fn parse(s: &str) -> Option<Self> {
+        DateTime::parse_from_rfc3339(s)
+            .map(|t| t.naive_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(s, NAIVE_FORMAT))
+            .ok()
+            .map(FrameTime)
+    }
+
+    fn from_scalar(v: &WundergraphScalarValue) -> Option<Self> {
+        match *v {
+            WundergraphScalarValue::SmallInt(i) => Self::from_seconds(f64::from(i)),
+            WundergraphScalarValue::Int(i) => Self::from_seconds(f64::from(i)),
+            WundergraphScalarValue::BigInt(i) => Self::from_seconds(i as f64),
+            WundergraphScalarValue::Float(f) => Self::from_seconds(f64::from(f)),
+            WundergraphScalarValue::Double(f) => Self::from_seconds(f),
+            WundergraphScalarValue::String(ref s) => Self::parse(s),
+            WundergraphScalarValue::Boolean(_) => None,
+        }
+    }
+}
+
+impl ToSql<Timestamp, Pg> for FrameTime {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<'_, W, Pg>) -> serialize::Result {
+        ToSql::<Timestamp, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Timestamp, Pg> for FrameTime {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        <NaiveDateTime as FromSql<Timestamp, Pg>>::from_sql(bytes).map(FrameTime)
+    }
+}
+
+impl WundergraphValue for FrameTime {
+    type PlaceHolder = PlaceHolder<Self>;
+    type SqlType = Nullable<Timestamp>;
+}
+
+impl<C> FilterValue<C> for FrameTime {
+    type RawValue = Self;
+    type AdditionalFilter = FrameTimeFilter<C>;
+}
+
+impl<C, DB, Ctx> AsColumnFilter<C, DB, Ctx> for FrameTime {
+    type Filter = FilterOption<Self, C>;
+}
+
+impl Nameable for FrameTime {
+    fn name() -> String {
+        String::from("FrameTime")
+    }
+}
+
+impl FromLookAheadValue for FrameTime {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        if let LookAheadValue::Scalar(s) = *v {
+            Self::from_scalar(s)
+        } else {
+            None
+        }
+    }
+}
+
+graphql_scalar!(FrameTime as "FrameTime" where Scalar = WundergraphScalarValue {
+    description: "Seconds since the epoch (UTC), RFC 3339 strings are accepted as input"
+
+    resolve(&self) -> Value {
+        Value::scalar(self.seconds())
+    }
+
+    from_input_value(v: &InputValue) -> Option<FrameTime> {
+        v.as_scalar().and_then(FrameTime::from_scalar)
+    }
+
+    from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, WundergraphScalarValue> {
+        match value {
+            // Checked here so invalid literals fail validation
+            ScalarToken::String(s) if FrameTime::parse(s).is_some() => {
+                Ok(WundergraphScalarValue::String(s.to_owned()))
+            }
+            ScalarToken::String(_) => Err(ParseError::UnexpectedToken(Token::Scalar(value))),
+            ScalarToken::Int(_) | ScalarToken::Float(_) => {
+                <f64 as ParseScalarValue<WundergraphScalarValue>>::from_str(value)
+            }
+        }
+    }
+});
+
+/// Time column of a table of frames, grouped in series by a reference to
+/// the movie they belong to
+///
+/// Frames are ordered by time then primary key, so frames sharing a time
+/// still have a deterministic order.
+pub trait TimeSeries: Column {
+    /// Entity of the frames
+    type Frame;
+    /// Primary key of the frames
+    type Key: Column<Table = Self::Table> + Default + QueryFragment<Pg>;
+    /// Column of the same table referencing the movie
+    type Series: Column<Table = Self::Table> + Default + QueryFragment<Pg>;
+    /// Primary key of the movies
+    type Movie: Column + Default;
+}
+
+impl TimeSeries for images::time {
+    type Frame = Image;
+    type Key = images::id;
+    type Series = images::color_movie_id;
+    type Movie = color_movies::id;
+}
+
+impl TimeSeries for vector_data::time {
+    type Frame = VectorData;
+    type Key = vector_data::id;
+    type Series = vector_data::vector_movie_id;
+    type Movie = vector_movies::id;
+}
+
+/// Operators added to the filter of `FrameTime` fields
+///
+/// * `gt`, `gte`, `lt`, `lte`: compare with the given time
+/// * `atOrBefore`, `atOrAfter`: the last frame of each movie at or before
+///   the given time, the first one at or after it
+/// * `nearest`: the `atOrBefore` frame of each movie, or the `atOrAfter`
+///   one when it is strictly closer to the given time
+///
+/// Frame lookups keep one frame per movie, of frames sharing a time the
+/// last one is at or before and the first one at or after, in primary key
+/// order.
+#[derive(Debug, Clone)]
+pub struct FrameTimeFilter<C> {
+    gt: Option<FrameTime>,
+    gte: Option<FrameTime>,
+    lt: Option<FrameTime>,
+    lte: Option<FrameTime>,
+    at_or_before: Option<FrameTime>,
+    at_or_after: Option<FrameTime>,
+    nearest: Option<FrameTime>,
+    column: PhantomData<C>,
+}
+
+impl<C> Nameable for FrameTimeFilter<C> {
+    fn name() -> String {
+        String::from("FrameTimeFilter")
+    }
+}
+
+impl<C> InnerFilter for FrameTimeFilter<C> {
+    type Context = ();
+
+    const FIELD_COUNT: usize = 7;
+
+    fn from_inner_input_value(
+        obj: IndexMap<&str, &InputValue<WundergraphScalarValue>>,
+    ) -> Option<Self> {
+        let arg = |name: &str| match obj.get(name) {
+            Some(v) => Option::<FrameTime>::from_input_value(*v),
+            None => Some(None),
+        };
+        Some(Self {
+            gt: arg("gt")?,
+            gte: arg("gte")?,
+            lt: arg("lt")?,
+            lte: arg("lte")?,
+            at_or_before: arg("atOrBefore")?,
+            at_or_after: arg("atOrAfter")?,
+            nearest: arg("nearest")?,
+            column: PhantomData,
+        })
+    }
+
+    fn from_inner_look_ahead(obj: &[(&str, LookAheadValue<'_, WundergraphScalarValue>)]) -> Self {
+        let arg = |name: &str| {
+            obj.iter()
+                .find(|(field, _)| *field == name)
+                .and_then(|(_, value)| FrameTime::from_look_ahead(value))
+        };
+        Self {
+            gt: arg("gt"),
+            gte: arg("gte"),
+            lt: arg("lt"),
+            lte: arg("lte"),
+            at_or_before: arg("atOrBefore"),
+            at_or_after: arg("atOrAfter"),
+            nearest: arg("nearest"),
+            column: PhantomData,
+        }
+    }
+
+    fn to_inner_input_value(&self, v: &mut IndexMap<&str, InputValue<WundergraphScalarValue>>) {
+        v.insert("gt", self.gt.to_input_value());
+        v.insert("gte", self.gte.to_input_value());
+        v.insert("lt", self.lt.to_input_value());
+        v.insert("lte", self.lte.to_input_value());
+        v.insert("atOrBefore", self.at_or_before.to_input_value());
+        v.insert("atOrAfter", self.at_or_after.to_input_value());
+        v.insert("nearest", self.nearest.to_input_value());
+    }
+
+    fn register_fields<'r>(
+        _info: &NameBuilder<Self>,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> Vec<Argument<'r, WundergraphScalarValue>> {
+        let gt = registry
+            .arg::<Option<FrameTime>>("gt", &())
+            .description("After this time");
+        let gte = registry
+            .arg::<Option<FrameTime>>("gte", &())
+            .description("At or after this time");
+        let lt = registry
+            .arg::<Option<FrameTime>>("lt", &())
+            .description("Before this time");
+        let lte = registry
+            .arg::<Option<FrameTime>>("lte", &())
+            .description("At or before this time");
+        let at_or_before = registry
+            .arg::<Option<FrameTime>>("atOrBefore", &())
+            .description("The last frame of the movie at or before this time");
+        let at_or_after = registry
+            .arg::<Option<FrameTime>>("atOrAfter", &())
+            .description("The first frame of the movie at or after this time");
+        let nearest = registry
+            .arg::<Option<FrameTime>>("nearest", &())
+            .description("The frame of the movie closest to this time");
+        vec![gt, gte, lt, lte, at_or_before, at_or_after, nearest]
+    }
+}
+
+impl<C> BuildFilter<Pg> for FrameTimeFilter<C>
+where
+    C: TimeSeries + Default + QueryFragment<Pg> + NonAggregate,
+    C::Table: HasTable<Table = C::Table>,
+    <C::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+    <C::Movie as Column>::Table: HasTable<Table = <C::Movie as Column>::Table>,
+    <<C::Movie as Column>::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+{
+    type Ret = FrameTimePredicate<C>;
+
+    fn into_filter(self) -> Option<Self::Ret> {
+        let conditions = [
+            (self.gt, TimeCondition::Gt),
+            (self.gte, TimeCondition::Gte),
+            (self.lt, TimeCondition::Lt),
+            (self.lte, TimeCondition::Lte),
+            (self.at_or_before, TimeCondition::AtOrBefore),
+            (self.at_or_after, TimeCondition::AtOrAfter),
+            (self.nearest, TimeCondition::Nearest),
+        ]
+        .iter()
+        .filter_map(|(time, condition)| time.map(|t| (*condition, t)))
+        .collect::<Vec<_>>();
+        if conditions.is_empty() {
+            None
+        } else {
+            Some(FrameTimePredicate {
+                column: C::default(),
+                conditions,
+                movies: None,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TimeCondition {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    AtOrBefore,
+    AtOrAfter,
+    Nearest,
+}
+
+/// Conjunction of conditions on a frame time column
+#[derive(Debug, Clone)]
+pub struct FrameTimePredicate<C> {
+    column: C,
+    conditions: Vec<(TimeCondition, FrameTime)>,
+    /// Only look frames up in these movies, all of them when `None`
+    movies: Option<Vec<i32>>,
+}
+
+impl<C: Default> FrameTimePredicate<C> {
+    /// The frame picked by `condition` in each of the given movies
+    pub(crate) fn lookup(condition: TimeCondition, time: FrameTime, movies: Vec<i32>) -> Self {
+        FrameTimePredicate {
+            column: C::default(),
+            conditions: vec![(condition, time)],
+            movies: Some(movies),
+        }
+    }
+}
+
+impl<C> Expression for FrameTimePredicate<C> {
+    type SqlType = Bool;
+}
+
+impl<C> NonAggregate for FrameTimePredicate<C> {}
+
+impl<C, QS> AppearsOnTable<QS> for FrameTimePredicate<C> where C: AppearsOnTable<QS> {}
+
+impl<C, QS> SelectableExpression<QS> for FrameTimePredicate<C> where C: SelectableExpression<QS> {}
+
+impl<C> QueryId for FrameTimePredicate<C> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<C> QueryFragment<Pg> for FrameTimePredicate<C>
+where
+    C: TimeSeries + QueryFragment<Pg>,
+    C::Table: HasTable<Table = C::Table>,
+    <C::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+    <C::Movie as Column>::Table: HasTable<Table = <C::Movie as Column>::Table>,
+    <<C::Movie as Column>::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        out.push_sql("(");
+        for (i, (condition, time)) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(" AND ");
+            }
+            let op = match condition {
+                TimeCondition::Gt => " > ",
+                TimeCondition::Gte => " >= ",
+                TimeCondition::Lt => " < ",
+                TimeCondition::Lte => " <= ",
+                _ => {
+                    self.walk_frame_lookup(*condition, time, out.reborrow())?;
+                    continue;
+                }
+            };
+            self.column.walk_ast(out.reborrow())?;
+            out.push_sql(op);
+            out.push_bind_param::<Timestamp, _>(time)?;
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<C> FrameTimePredicate<C>
+where
+    C: TimeSeries + QueryFragment<Pg>,
+    C::Table: HasTable<Table = C::Table>,
+    <C::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+    <C::Movie as Column>::Table: HasTable<Table = <C::Movie as Column>::Table>,
+    <<C::Movie as Column>::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+{
+    /// `key IN (SELECT (<frame of the movie>) FROM movies AS movie)`
+    ///
+    /// The frame is looked up once per movie with the `(series, time, key)`
+    /// index, rather than once per row of the outer query.
+    fn walk_frame_lookup(
+        &self,
+        condition: TimeCondition,
+        time: &FrameTime,
+        mut out: AstPass<'_, Pg>,
+    ) -> QueryResult<()> {
+        C::Key::default().walk_ast(out.reborrow())?;
+        out.push_sql(" IN (SELECT (");
+        match condition {
+            TimeCondition::AtOrBefore => {
+                Self::walk_closest(" <= ", " DESC", None, time, out.reborrow())?
+            }
+            TimeCondition::AtOrAfter => Self::walk_closest(" >= ", "", None, time, out.reborrow())?,
+            _ => {
+                // Candidates are ranked by distance then side, the frame
+                // before wins ties
+                out.push_sql("SELECT candidate.");
+                out.push_identifier(C::Key::NAME)?;
+                out.push_sql(" FROM ((");
+                Self::walk_closest(" <= ", " DESC", Some("0"), time, out.reborrow())?;
+                out.push_sql(") UNION ALL (");
+                Self::walk_closest(" >= ", "", Some("1"), time, out.reborrow())?;
+                out.push_sql(")) AS candidate(");
+                out.push_identifier(C::Key::NAME)?;
+                out.push_sql(", ");
+                out.push_identifier(C::NAME)?;
+                out.push_sql(", side) ORDER BY abs(extract(epoch FROM candidate.");
+                out.push_identifier(C::NAME)?;
+                out.push_sql(" - ");
+                out.push_bind_param::<Timestamp, _>(time)?;
+                out.push_sql(")), candidate.side LIMIT 1");
+            }
+        }
+        out.push_sql(") FROM ");
+        <C::Movie as Column>::Table::table()
+            .from_clause()
+            .walk_ast(out.reborrow())?;
+        out.push_sql(" AS movie");
+        if let Some(ref movies) = self.movies {
+            out.push_sql(" WHERE movie.");
+            out.push_identifier(C::Movie::NAME)?;
+            out.push_sql(" = ANY(");
+            out.push_bind_param::<Array<Int4>, _>(movies)?;
+            out.push_sql(")");
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+
+    /// `SELECT frame.key FROM frames AS frame WHERE frame.series = movie.id
+    /// AND frame.time <op> $time ORDER BY frame.time <order>, frame.key
+    /// <order> LIMIT 1`, also selecting the time and `side` for `nearest`
+    fn walk_closest(
+        op: &str,
+        order: &str,
+        side: Option<&str>,
+        time: &FrameTime,
+        mut out: AstPass<'_, Pg>,
+    ) -> QueryResult<()> {
+        out.push_sql("SELECT frame.");
+        out.push_identifier(C::Key::NAME)?;
+        if let Some(side) = side {
+            out.push_sql(", frame.");
+            out.push_identifier(C::NAME)?;
+            out.push_sql(", ");
+            out.push_sql(side);
+        }
+        out.push_sql(" FROM ");
+        C::Table::table().from_clause().walk_ast(out.reborrow())?;
+        out.push_sql(" AS frame WHERE frame.");
+        out.push_identifier(C::Series::NAME)?;
+        out.push_sql(" = movie.");
+        out.push_identifier(C::Movie::NAME)?;
+        out.push_sql(" AND frame.");
+        out.push_identifier(C::NAME)?;
+        out.push_sql(op);
+        out.push_bind_param::<Timestamp, _>(time)?;
+        out.push_sql(" ORDER BY frame.");
+        out.push_identifier(C::NAME)?;
+        out.push_sql(order);
+        out.push_sql(", frame.");
+        out.push_identifier(C::Key::NAME)?;
+        out.push_sql(order);
+        out.push_sql(" LIMIT 1");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::{debug_query, QueryDsl};
+    use juniper::GraphQLType;
+
+    #[test]
+    fn seconds() {
+        let time = FrameTime::from_seconds(1.25).unwrap();
+        assert_eq!(time.0, NaiveDateTime::from_timestamp(1, 250_000_000));
+        assert!((time.seconds() - 1.25).abs() < 1e-9);
+        assert_eq!(FrameTime::from_seconds(f64::NAN), None);
+    }
+
+    #[test]
+    fn parse() {
+        let expected = FrameTime(NaiveDateTime::from_timestamp(3600, 500_000_000));
+        assert_eq!(
+            FrameTime::parse("1970-01-01T02:00:00.5+01:00"),
+            Some(expected)
+        );
+        assert_eq!(FrameTime::parse("1970-01-01T01:00:00.5"), Some(expected));
+        assert_eq!(FrameTime::parse("yesterday"), None);
+    }
+
+    #[test]
+    fn scalar_name() {
+        let name = <FrameTime as GraphQLType<WundergraphScalarValue>>::name(&());
+        assert_eq!(name, Some("FrameTime"));
+        assert_eq!(<FrameTime as Nameable>::name(), "FrameTime");
+    }
+
+    #[test]
+    fn invalid_literals() {
+        let parse = <FrameTime as ParseScalarValue<WundergraphScalarValue>>::from_str;
+        assert!(parse(ScalarToken::String("1970-01-01T00:00:00Z")).is_ok());
+        assert!(parse(ScalarToken::Float("1.5")).is_ok());
+        assert!(parse(ScalarToken::String("yesterday")).is_err());
+    }
+
+    #[test]
+    fn lookup_sql() {
+        let time = FrameTime::from_seconds(10.0).unwrap();
+        let before =
+            FrameTimePredicate::<images::time>::lookup(TimeCondition::AtOrBefore, time, vec![1, 2]);
+        let sql =
+            debug_query::<Pg, _>(&images::table.select(images::id).filter(before)).to_string();
+        assert_eq!(
+            sql,
+            "SELECT \"images\".\"id\" FROM \"images\" WHERE (\"images\".\"id\" IN (SELECT (\
+             SELECT frame.\"id\" FROM \"images\" AS frame \
+             WHERE frame.\"color_movie_id\" = movie.\"id\" AND frame.\"time\" <= $1 \
+             ORDER BY frame.\"time\" DESC, frame.\"id\" DESC LIMIT 1) \
+             FROM \"color_movies\" AS movie WHERE movie.\"id\" = ANY($2))) \
+             -- binds: [FrameTime(1970-01-01T00:00:10), [1, 2]]"
+        );
+
+        let nearest = FrameTimeFilter::<vector_data::time> {
+            gt: None,
+            gte: None,
+            lt: None,
+            lte: None,
+            at_or_before: None,
+            at_or_after: None,
+            nearest: Some(time),
+            column: PhantomData,
+        }
+        .into_filter()
+        .unwrap();
+        let sql = debug_query::<Pg, _>(&vector_data::table.select(vector_data::id).filter(nearest))
+            .to_string();
+        assert_eq!(
+            sql,
+            "SELECT \"vector_data\".\"id\" FROM \"vector_data\" WHERE (\"vector_data\".\"id\" IN (\
+             SELECT (SELECT candidate.\"id\" FROM ((\
+             SELECT frame.\"id\", frame.\"time\", 0 FROM \"vector_data\" AS frame \
+             WHERE frame.\"vector_movie_id\" = movie.\"id\" AND frame.\"time\" <= $1 \
+             ORDER BY frame.\"time\" DESC, frame.\"id\" DESC LIMIT 1) UNION ALL (\
+             SELECT frame.\"id\", frame.\"time\", 1 FROM \"vector_data\" AS frame \
+             WHERE frame.\"vector_movie_id\" = movie.\"id\" AND frame.\"time\" >= $2 \
+             ORDER BY frame.\"time\", frame.\"id\" LIMIT 1)) AS candidate(\"id\", \"time\", side) \
+             ORDER BY abs(extract(epoch FROM candidate.\"time\" - $3)), candidate.side LIMIT 1) \
+             FROM \"vector_movies\" AS movie)) \
+             -- binds: [FrameTime(1970-01-01T00:00:10), FrameTime(1970-01-01T00:00:10), \
+             FrameTime(1970-01-01T00:00:10)]"
+        );
+    }
+}
@@ -1,7 +1,14 @@
 use wundergraph::query_builder::types::{HasMany, HasOne};
 use wundergraph::scalar::WundergraphScalarValue;
 use wundergraph::WundergraphEntity;
-// use serde_json::Value as JsonValue;
+use crate::bounding_box::{BoundingBox, BoundingBoxInput};
+use crate::colormap::{colormap_columns, ColormapChangeset, NewColormap, StoredGradient};
+use crate::frame_lookup::{FrameAt, FrameTimeline, FramesBetween};
+use crate::frame_time::FrameTime;
+use crate::json::Json;
+use crate::links::Link;
+use crate::movie_content::MovieContents;
+use crate::ColorFormat;
 
 table! {
     cinemas (id) {
@@ -33,6 +40,7 @@ table! {
         id -> Int4,
         format -> Int2,
         default_colormap -> Int4,
+        movie_id -> Nullable<Int4>,
     }
 }
 
@@ -64,6 +72,14 @@ table! {
     }
 }
 
+table! {
+    movie_contents (id) {
+        id -> Int4,
+        color_movie_id -> Nullable<Int4>,
+        vector_movie_id -> Nullable<Int4>,
+    }
+}
+
 table! {
     movies (id) {
         id -> Int4,
@@ -102,7 +118,7 @@ table! {
     vector_data (id) {
         id -> Int4,
         time -> Timestamp,
-        // properties -> Nullable<Jsonb>,
+        properties -> Nullable<Jsonb>,
         vector_movie_id -> Int4,
     }
 }
@@ -111,6 +127,7 @@ table! {
     vector_movies (id) {
         id -> Int4,
         default_style -> Nullable<Int4>,
+        movie_id -> Nullable<Int4>,
     }
 }
 
@@ -118,7 +135,7 @@ table! {
     vector_styles (id) {
         id -> Int4,
         name -> Text,
-        // style -> Jsonb,
+        style -> Jsonb,
     }
 }
 
@@ -138,6 +155,7 @@ allow_tables_to_appear_in_same_query!(
     colormaps,
     images,
     images_tags_values,
+    movie_contents,
     movies,
     movies_tags,
     tags,
@@ -156,6 +174,7 @@ pub struct Cinema {
     id: i32,
     name: String,
     cinemas_movies: HasMany<CinemasMovie, cinemas_movies::cinema_id>,
+    movies: HasMany<Movie, Link<cinemas_movies::cinema_id, cinemas_movies::movie_id, movies::id>>,
 }
 
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
@@ -164,7 +183,7 @@ pub struct Cinema {
 pub struct CinemasMovie {
     id: i32,
     exposed_format: Option<i32>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBox>,
     cinema_id: HasOne<i32, Cinema>,
     movie_id: HasOne<i32, Movie>,
 }
@@ -183,20 +202,31 @@ pub struct ColorMovieColormap {
 #[primary_key(id)]
 pub struct ColorMovie {
     id: i32,
-    format: i16,
+    format: ColorFormat,
     default_colormap: HasOne<i32, Colormap>,
+    movie_id: Option<HasOne<i32, Movie>>,
     color_movie_colormap: HasMany<ColorMovieColormap, color_movie_colormap::color_movie_id>,
+    colormaps: HasMany<Colormap, Link<color_movie_colormap::color_movie_id, color_movie_colormap::colormap_id, colormaps::id>>,
     images: HasMany<Image, images::color_movie_id>,
+    /// Images within `[from, to)`, in time order
+    #[wundergraph(graphql_name = "imagesBetween")]
+    images_between: HasMany<FramesBetween<Image>, images::color_movie_id>,
+    /// The image picked by `mode` at `time`, none or one
+    #[wundergraph(graphql_name = "imageAt")]
+    image_at: HasMany<FrameAt<Image>, images::color_movie_id>,
+    /// Images counted in buckets of equal duration, with the first of each
+    timeline: HasMany<FrameTimeline<Image>, images::color_movie_id>,
 }
 
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
-#[table_name = "colormaps"]
+#[table_name = "colormap_columns"]
 #[primary_key(id)]
 pub struct Colormap {
     id: i32,
     name: String,
     colors: Vec<String>,
     positions: Vec<f64>,
+    gradient: StoredGradient,
     color_movie_colormap: HasMany<ColorMovieColormap, color_movie_colormap::colormap_id>,
     color_movies: HasMany<ColorMovie, color_movies::default_colormap>,
 }
@@ -206,11 +236,13 @@ pub struct Colormap {
 #[primary_key(id)]
 pub struct Image {
     id: i32,
-    time: chrono::naive::NaiveDateTime,
+    time: FrameTime,
     path: String,
-    box_: Option<Vec<f64>>,
+    box_: Option<BoundingBox>,
     color_movie_id: HasOne<i32, ColorMovie>,
     images_tags_values: HasMany<ImagesTagsValue, images_tags_values::image_id>,
+    #[wundergraph(graphql_name = "tagValues")]
+    tag_values: HasMany<TagsValue, Link<images_tags_values::image_id, images_tags_values::tags_value_id, tags_values::id>>,
 }
 
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
@@ -222,6 +254,20 @@ pub struct ImagesTagsValue {
     image_id: HasOne<i32, Image>,
 }
 
+/// Ids of the content of a movie, read from the `movie_contents` view
+///
+/// Only used to filter `Movie.content`, which resolves to the
+/// `MovieContent` union instead.
+#[derive(Clone, Debug, Identifiable, WundergraphEntity)]
+#[table_name = "movie_contents"]
+#[primary_key(id)]
+pub struct MovieContentIds {
+    /// Id of the movie
+    id: i32,
+    color_movie_id: Option<i32>,
+    vector_movie_id: Option<i32>,
+}
+
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
 #[table_name = "movies"]
 #[primary_key(id)]
@@ -230,10 +276,15 @@ pub struct Movie {
     identifier: String,
     name: String,
     description: Option<String>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBox>,
     path: Option<String>,
     cinemas_movies: HasMany<CinemasMovie, cinemas_movies::movie_id>,
+    color_movies: HasMany<ColorMovie, color_movies::movie_id>,
+    /// The color and vector movies linked to the movie
+    content: HasMany<MovieContents, movie_contents::id>,
     movies_tags: HasMany<MoviesTag, movies_tags::movie_id>,
+    tags: HasMany<Tag, Link<movies_tags::movie_id, movies_tags::tag_id, tags::id>>,
+    vector_movies: HasMany<VectorMovie, vector_movies::movie_id>,
 }
 
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
@@ -270,8 +321,8 @@ pub struct TagsValue {
 #[primary_key(id)]
 pub struct VectorData {
     id: i32,
-    time: chrono::naive::NaiveDateTime,
-    // properties: Option<JsonValue>,
+    time: FrameTime,
+    properties: Option<Json>,
     vector_movie_id: HasOne<i32, VectorMovie>,
 }
 
@@ -281,8 +332,18 @@ pub struct VectorData {
 pub struct VectorMovie {
     id: i32,
     default_style: Option<HasOne<i32, VectorStyle>>,
+    movie_id: Option<HasOne<i32, Movie>>,
     vector_data: HasMany<VectorData, vector_data::vector_movie_id>,
+    /// Vector data within `[from, to)`, in time order
+    #[wundergraph(graphql_name = "vectorDataBetween")]
+    vector_data_between: HasMany<FramesBetween<VectorData>, vector_data::vector_movie_id>,
+    /// The vector data picked by `mode` at `time`, none or one
+    #[wundergraph(graphql_name = "vectorDataAt")]
+    vector_data_at: HasMany<FrameAt<VectorData>, vector_data::vector_movie_id>,
+    /// Vector data counted in buckets of equal duration, with the first of each
+    timeline: HasMany<FrameTimeline<VectorData>, vector_data::vector_movie_id>,
     vector_styles_vector_movies: HasMany<VectorStylesVectorMovie, vector_styles_vector_movies::vector_movie_id>,
+    styles: HasMany<VectorStyle, Link<vector_styles_vector_movies::vector_movie_id, vector_styles_vector_movies::vector_style_id, vector_styles::id>>,
 }
 
 #[derive(Clone, Debug, Identifiable, WundergraphEntity)]
@@ -291,7 +352,7 @@ pub struct VectorMovie {
 pub struct VectorStyle {
     id: i32,
     name: String,
-    // style: JsonValue,
+    style: Json,
     vector_movies: HasMany<VectorMovie, vector_movies::default_style>,
     vector_styles_vector_movies: HasMany<VectorStylesVectorMovie, vector_styles_vector_movies::vector_style_id>,
 }
@@ -349,7 +410,7 @@ pub struct CinemaChangeset {
 #[table_name = "cinemas_movies"]
 pub struct NewCinemasMovie {
     exposed_format: Option<i32>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBoxInput>,
     cinema_id: i32,
     movie_id: i32,
 }
@@ -361,7 +422,7 @@ pub struct NewCinemasMovie {
 pub struct CinemasMovieChangeset {
     id: i32,
     exposed_format: Option<i32>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBoxInput>,
     cinema_id: i32,
     movie_id: i32,
 }
@@ -388,8 +449,9 @@ pub struct ColorMovieColormapChangeset {
 #[graphql(scalar = "WundergraphScalarValue")]
 #[table_name = "color_movies"]
 pub struct NewColorMovie {
-    format: i16,
+    format: ColorFormat,
     default_colormap: i32,
+    movie_id: Option<i32>,
 }
 
 #[derive(AsChangeset, Identifiable, juniper::GraphQLInputObject, Clone, Debug)]
@@ -398,37 +460,18 @@ pub struct NewColorMovie {
 #[primary_key(id)]
 pub struct ColorMovieChangeset {
     id: i32,
-    format: i16,
+    format: ColorFormat,
     default_colormap: i32,
-}
-
-#[derive(Insertable, juniper::GraphQLInputObject, Clone, Debug)]
-#[graphql(scalar = "WundergraphScalarValue")]
-#[table_name = "colormaps"]
-pub struct NewColormap {
-    name: String,
-    colors: Vec<String>,
-    positions: Vec<f64>,
-}
-
-#[derive(AsChangeset, Identifiable, juniper::GraphQLInputObject, Clone, Debug)]
-#[graphql(scalar = "WundergraphScalarValue")]
-#[table_name = "colormaps"]
-#[primary_key(id)]
-pub struct ColormapChangeset {
-    id: i32,
-    name: String,
-    colors: Vec<String>,
-    positions: Vec<f64>,
+    movie_id: Option<i32>,
 }
 
 #[derive(Insertable, juniper::GraphQLInputObject, Clone, Debug)]
 #[graphql(scalar = "WundergraphScalarValue")]
 #[table_name = "images"]
 pub struct NewImage {
-    time: chrono::naive::NaiveDateTime,
+    time: FrameTime,
     path: String,
-    box_: Option<Vec<f64>>,
+    box_: Option<BoundingBoxInput>,
     color_movie_id: i32,
 }
 
@@ -438,9 +481,9 @@ pub struct NewImage {
 #[primary_key(id)]
 pub struct ImageChangeset {
     id: i32,
-    time: chrono::naive::NaiveDateTime,
+    time: FrameTime,
     path: String,
-    box_: Option<Vec<f64>>,
+    box_: Option<BoundingBoxInput>,
     color_movie_id: i32,
 }
 
@@ -469,7 +512,7 @@ pub struct NewMovie {
     identifier: String,
     name: String,
     description: Option<String>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBoxInput>,
     path: Option<String>,
 }
 
@@ -482,7 +525,7 @@ pub struct MovieChangeset {
     identifier: String,
     name: String,
     description: Option<String>,
-    pixels_box: Option<Vec<f64>>,
+    pixels_box: Option<BoundingBoxInput>,
     path: Option<String>,
 }
 
@@ -542,8 +585,8 @@ pub struct TagsValueChangeset {
 #[graphql(scalar = "WundergraphScalarValue")]
 #[table_name = "vector_data"]
 pub struct NewVectorData {
-    time: chrono::naive::NaiveDateTime,
-    // properties: Option<JsonValue>,
+    time: FrameTime,
+    properties: Option<Json>,
     vector_movie_id: i32,
 }
 
@@ -553,8 +596,8 @@ pub struct NewVectorData {
 #[primary_key(id)]
 pub struct VectorDataChangeset {
     id: i32,
-    time: chrono::naive::NaiveDateTime,
-    // properties: Option<JsonValue>,
+    time: FrameTime,
+    properties: Option<Json>,
     vector_movie_id: i32,
 }
 
@@ -563,6 +606,7 @@ pub struct VectorDataChangeset {
 #[table_name = "vector_movies"]
 pub struct NewVectorMovie {
     default_style: Option<i32>,
+    movie_id: Option<i32>,
 }
 
 #[derive(AsChangeset, Identifiable, juniper::GraphQLInputObject, Clone, Debug)]
@@ -572,6 +616,7 @@ pub struct NewVectorMovie {
 pub struct VectorMovieChangeset {
     id: i32,
     default_style: Option<i32>,
+    movie_id: Option<i32>,
 }
 
 #[derive(Insertable, juniper::GraphQLInputObject, Clone, Debug)]
@@ -579,7 +624,7 @@ pub struct VectorMovieChangeset {
 #[table_name = "vector_styles"]
 pub struct NewVectorStyle {
     name: String,
-    // style: JsonValue,
+    style: Json,
 }
 
 #[derive(AsChangeset, Identifiable, juniper::GraphQLInputObject, Clone, Debug)]
@@ -589,7 +634,7 @@ pub struct NewVectorStyle {
 pub struct VectorStyleChangeset {
     id: i32,
     name: String,
-    // style: JsonValue,
+    style: Json,
 }
 
 #[derive(Insertable, juniper::GraphQLInputObject, Clone, Debug)]
@@ -0,0 +1,290 @@
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use diesel::r2d2::PoolError;
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::parser::{Lexer, Token};
+use juniper::{FieldError, InputValue};
+use serde::Deserialize;
+use std::fmt;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Failure outside of GraphQL execution
+///
+/// Rendered as a GraphQL response carrying a single error with a
+/// machine readable `extensions.code`.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// No connection could be acquired from the database pool
+    DatabaseUnavailable(PoolError),
+    /// The thread pool executing requests is gone
+    ExecutorUnavailable,
+    Internal(String),
+}
+
+impl ServiceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::DatabaseUnavailable(_) => "DATABASE_UNAVAILABLE",
+            ServiceError::ExecutorUnavailable => "EXECUTOR_UNAVAILABLE",
+            ServiceError::Internal(_) => "INTERNAL_SERVER_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::DatabaseUnavailable(e) => write!(f, "Database unavailable: {}", e),
+            ServiceError::ExecutorUnavailable => write!(f, "Request executor unavailable"),
+            ServiceError::Internal(e) => write!(f, "Internal error: {}", e),
+        }
+    }
+}
+
+impl From<BlockingError<ServiceError>> for ServiceError {
+    fn from(e: BlockingError<ServiceError>) -> Self {
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => ServiceError::ExecutorUnavailable,
+        }
+    }
+}
+
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServiceError::DatabaseUnavailable(_) | ServiceError::ExecutorUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ServiceError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let res = GraphQLResponse::<WundergraphScalarValue>::error(FieldError::new(
+            self,
+            graphql_value!({ "code": (self.code()) }),
+        ));
+        HttpResponse::build(self.status_code()).json(&res)
+    }
+}
+
+/// Query string of a `GET /graphql` request
+///
+/// `variables` is sent as a JSON encoded string as described by the
+/// GraphQL over HTTP spec.
+#[derive(Deserialize, Debug)]
+pub struct GraphQLQueryParams {
+    query: Option<String>,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<String>,
+}
+
+impl GraphQLQueryParams {
+    /// The operation that will be executed for this request
+    pub fn operation_kind(&self) -> Option<OperationKind> {
+        operation_kind(self.query.as_deref()?, self.operation_name.as_deref())
+    }
+
+    /// The request to execute, or the message of the error to answer with
+    pub fn into_request(self) -> Result<GraphQLRequest<WundergraphScalarValue>, String> {
+        let query = self
+            .query
+            .ok_or_else(|| String::from("Missing query parameter"))?;
+        let variables = match self.variables {
+            Some(ref v) if !v.trim().is_empty() => Some(
+                serde_json::from_str::<InputValue<WundergraphScalarValue>>(v)
+                    .map_err(|e| format!("Invalid variables: {}", e))?,
+            ),
+            _ => None,
+        };
+        Ok(GraphQLRequest::new(query, self.operation_name, variables))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+/// Find the type of the operation selected by `operation_name` in `query`
+///
+/// This only looks at the top level tokens of the document, returns `None`
+/// if the document can't be tokenized or the operation can't be selected
+/// and leaves reporting those errors to the executor.
+pub fn operation_kind(query: &str, operation_name: Option<&str>) -> Option<OperationKind> {
+    let mut operations = Vec::new();
+    let mut header: Option<(OperationKind, Option<&str>)> = None;
+    let mut expect_name = false;
+    let mut in_fragment = false;
+    let mut depth = 0usize;
+
+    for token in Lexer::new(query) {
+        let token = token.ok()?.item;
+        let after_keyword = expect_name;
+        expect_name = false;
+        match token {
+            Token::EndOfFile => break,
+            Token::CurlyOpen | Token::ParenOpen | Token::BracketOpen => {
+                if depth == 0 && token == Token::CurlyOpen && !in_fragment {
+                    operations.push(header.take().unwrap_or((OperationKind::Query, None)));
+                }
+                depth += 1;
+            }
+            Token::CurlyClose | Token::ParenClose | Token::BracketClose => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 && token == Token::CurlyClose {
+                    in_fragment = false;
+                }
+            }
+            Token::Name(name) if depth == 0 => {
+                if after_keyword {
+                    if let Some((_, ref mut op_name)) = header {
+                        *op_name = Some(name);
+                    }
+                } else if header.is_none() && !in_fragment {
+                    let kind = match name {
+                        "query" => Some(OperationKind::Query),
+                        "mutation" => Some(OperationKind::Mutation),
+                        "subscription" => Some(OperationKind::Subscription),
+                        "fragment" => {
+                            in_fragment = true;
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        header = Some((kind, None));
+                        expect_name = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match operation_name {
+        Some(name) => operations
+            .iter()
+            .find(|(_, op_name)| *op_name == Some(name))
+            .map(|(kind, _)| *kind),
+        None if operations.len() == 1 => Some(operations[0].0),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(query: &str) -> Option<OperationKind> {
+        operation_kind(query, None)
+    }
+
+    #[test]
+    fn shorthand_query() {
+        assert_eq!(kind("{ heros { id } }"), Some(OperationKind::Query));
+        assert_eq!(kind("query { heros { id } }"), Some(OperationKind::Query));
+        assert_eq!(
+            kind("query Heros { heros { id } }"),
+            Some(OperationKind::Query)
+        );
+    }
+
+    #[test]
+    fn mutation() {
+        assert_eq!(
+            kind("mutation { CreateHero(NewHero: {}) { id } }"),
+            Some(OperationKind::Mutation)
+        );
+        assert_eq!(
+            kind("mutation Create($name: String = \"x\") @skip(if: false) { a }"),
+            Some(OperationKind::Mutation)
+        );
+        assert_eq!(
+            kind("subscription { a }"),
+            Some(OperationKind::Subscription)
+        );
+    }
+
+    #[test]
+    fn comments_and_strings() {
+        assert_eq!(
+            kind("# mutation { a }\n{ heros(name: \"mutation { a }\") { id } }"),
+            Some(OperationKind::Query)
+        );
+        assert_eq!(
+            kind("{ heros(name: \"\\\"mutation\") { id } } # mutation"),
+            Some(OperationKind::Query)
+        );
+        assert_eq!(
+            kind("# query\nmutation { a }"),
+            Some(OperationKind::Mutation)
+        );
+    }
+
+    #[test]
+    fn fragments_before_the_operation() {
+        assert_eq!(
+            kind("fragment F on Hero { id } mutation { CreateHero { ...F } }"),
+            Some(OperationKind::Mutation)
+        );
+        assert_eq!(
+            kind("fragment mutation on Hero { id } { heros { ...mutation } }"),
+            Some(OperationKind::Query)
+        );
+        assert_eq!(
+            kind("fragment F on Hero { friends { id } } query { heros { ...F } }"),
+            Some(OperationKind::Query)
+        );
+    }
+
+    #[test]
+    fn operation_name() {
+        let doc = "query A { a } mutation B { b } { c }";
+        assert_eq!(operation_kind(doc, Some("A")), Some(OperationKind::Query));
+        assert_eq!(
+            operation_kind(doc, Some("B")),
+            Some(OperationKind::Mutation)
+        );
+        assert_eq!(operation_kind(doc, Some("C")), None);
+        // Several operations without a name to select one
+        assert_eq!(operation_kind(doc, None), None);
+    }
+
+    #[test]
+    fn invalid_documents() {
+        assert_eq!(kind(""), None);
+        assert_eq!(kind("{ a"), Some(OperationKind::Query));
+        assert_eq!(kind("{ a } }"), None);
+        assert_eq!(kind("{ \"unterminated }"), None);
+    }
+
+    #[test]
+    fn query_params() {
+        let params = GraphQLQueryParams {
+            query: None,
+            operation_name: None,
+            variables: None,
+        };
+        assert_eq!(params.operation_kind(), None);
+        assert_eq!(
+            params.into_request().unwrap_err(),
+            "Missing query parameter"
+        );
+
+        let params = GraphQLQueryParams {
+            query: Some(String::from("{ a }")),
+            operation_name: None,
+            variables: Some(String::from("{")),
+        };
+        assert!(params
+            .into_request()
+            .unwrap_err()
+            .starts_with("Invalid variables"));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::db::{self, DbPool};
+use diesel::{sql_query, RunQueryDsl};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Longest time a readiness probe waits for a database connection
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<String>,
+}
+
+impl ComponentHealth {
+    fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            error: None,
+            pending: Vec::new(),
+        }
+    }
+
+    fn error(e: impl Display) -> Self {
+        Self {
+            status: Status::Unavailable,
+            error: Some(e.to_string()),
+            pending: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+impl HealthReport {
+    fn new(components: BTreeMap<&'static str, ComponentHealth>) -> Self {
+        let status = if components.values().all(|c| c.status == Status::Ok) {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        };
+        Self { status, components }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+/// The process is up, nothing else is checked
+pub fn liveness() -> HealthReport {
+    HealthReport::new(BTreeMap::new())
+}
+
+/// Check the database answers and its schema is current
+///
+/// Migrations are compared with the versions recorded as applied, nothing
+/// is executed. They only move forward, once seen current
+/// `migrations_current` is set and they aren't checked again.
+pub fn readiness(pool: &DbPool, migrations_current: &AtomicBool) -> HealthReport {
+    let mut components = BTreeMap::new();
+    match pool.get_timeout(PROBE_TIMEOUT) {
+        Ok(conn) => {
+            let database = match sql_query("SELECT 1").execute(&*conn) {
+                Ok(_) => ComponentHealth::ok(),
+                Err(e) => ComponentHealth::error(e),
+            };
+            components.insert("database", database);
+
+            let migrations = if migrations_current.load(Ordering::Relaxed) {
+                ComponentHealth::ok()
+            } else {
+                match db::pending_migrations(&conn) {
+                    Ok(ref pending) if pending.is_empty() => {
+                        migrations_current.store(true, Ordering::Relaxed);
+                        ComponentHealth::ok()
+                    }
+                    Ok(pending) => ComponentHealth {
+                        pending,
+                        ..ComponentHealth::error("migrations pending")
+                    },
+                    Err(e) => ComponentHealth::error(e),
+                }
+            };
+            components.insert("migrations", migrations);
+        }
+        Err(e) => {
+            components.insert("database", ComponentHealth::error(e));
+            components.insert("migrations", ComponentHealth::error("database unavailable"));
+        }
+    }
+    HealthReport::new(components)
+}
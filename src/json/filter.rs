@@ -0,0 +1,357 @@
+use super::Json;
+use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::sql_types::{Array, Bool, Double, Jsonb, Text};
+use diesel::{Column, QueryResult};
+use indexmap::IndexMap;
+use juniper::meta::Argument;
+use juniper::{FromInputValue, InputValue, LookAheadValue, Registry, ToInputValue};
+use serde_json::Value as JsonValue;
+use std::marker::PhantomData;
+use wundergraph::juniper_ext::{FromLookAheadValue, NameBuilder, Nameable};
+use wundergraph::query_builder::selection::filter::{BuildFilter, InnerFilter};
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Condition on the value found at `path` inside a JSONB document
+#[derive(Debug, Clone, PartialEq, GraphQLInputObject)]
+#[graphql(scalar = "WundergraphScalarValue")]
+pub struct JsonPathFilter {
+    /// Object keys or array indices leading to the value
+    pub path: Vec<String>,
+    /// The value exists (or not) at `path`
+    pub exists: Option<bool>,
+    /// The value at `path`, as text, equals this one
+    pub eq: Option<String>,
+    pub gt: Option<f64>,
+    pub gte: Option<f64>,
+    pub lt: Option<f64>,
+    pub lte: Option<f64>,
+}
+
+impl FromLookAheadValue for JsonPathFilter {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        if let LookAheadValue::Object(ref fields) = *v {
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| *field == name)
+                    .map(|(_, value)| value)
+            };
+            Some(Self {
+                path: field("path").and_then(Vec::<String>::from_look_ahead)?,
+                exists: field("exists").and_then(bool::from_look_ahead),
+                eq: field("eq").and_then(String::from_look_ahead),
+                gt: field("gt").and_then(f64::from_look_ahead),
+                gte: field("gte").and_then(f64::from_look_ahead),
+                lt: field("lt").and_then(f64::from_look_ahead),
+                lte: field("lte").and_then(f64::from_look_ahead),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// JSONB operators added to the filter of `Json` fields
+///
+/// * `contains`: the document contains this one (`@>`)
+/// * `hasKey`: the top level object has this key (`?`)
+/// * `path`: conditions on nested values (`#>`, `#>>`)
+#[derive(Debug, Clone)]
+pub struct JsonbFilter<C> {
+    contains: Option<Json>,
+    has_key: Option<String>,
+    path: Option<Vec<JsonPathFilter>>,
+    column: PhantomData<C>,
+}
+
+impl<C> Nameable for JsonbFilter<C> {
+    fn name() -> String {
+        String::from("JsonbFilter")
+    }
+}
+
+impl<C> InnerFilter for JsonbFilter<C> {
+    type Context = ();
+
+    const FIELD_COUNT: usize = 3;
+
+    fn from_inner_input_value(
+        obj: IndexMap<&str, &InputValue<WundergraphScalarValue>>,
+    ) -> Option<Self> {
+        let contains = match obj.get("contains") {
+            Some(v) => Option::<Json>::from_input_value(*v)?,
+            None => None,
+        };
+        let has_key = match obj.get("hasKey") {
+            Some(v) => Option::<String>::from_input_value(*v)?,
+            None => None,
+        };
+        let path = match obj.get("path") {
+            Some(v) => Option::<Vec<JsonPathFilter>>::from_input_value(*v)?,
+            None => None,
+        };
+        Some(Self {
+            contains,
+            has_key,
+            path,
+            column: PhantomData,
+        })
+    }
+
+    fn from_inner_look_ahead(obj: &[(&str, LookAheadValue<'_, WundergraphScalarValue>)]) -> Self {
+        let field = |name: &str| {
+            obj.iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value)
+        };
+        Self {
+            contains: field("contains").and_then(Json::from_look_ahead),
+            has_key: field("hasKey").and_then(String::from_look_ahead),
+            path: field("path").and_then(Vec::<JsonPathFilter>::from_look_ahead),
+            column: PhantomData,
+        }
+    }
+
+    fn to_inner_input_value(&self, v: &mut IndexMap<&str, InputValue<WundergraphScalarValue>>) {
+        v.insert("contains", self.contains.to_input_value());
+        v.insert("hasKey", self.has_key.to_input_value());
+        v.insert("path", self.path.to_input_value());
+    }
+
+    fn register_fields<'r>(
+        _info: &NameBuilder<Self>,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> Vec<Argument<'r, WundergraphScalarValue>> {
+        let contains = registry
+            .arg::<Option<Json>>("contains", &())
+            .description("The document contains this JSON value");
+        let has_key = registry
+            .arg::<Option<String>>("hasKey", &())
+            .description("The top level object has this key");
+        let path = registry
+            .arg::<Option<Vec<JsonPathFilter>>>("path", &())
+            .description("Conditions on values nested in the document, all must match");
+        vec![contains, has_key, path]
+    }
+}
+
+impl<C> BuildFilter<Pg> for JsonbFilter<C>
+where
+    C: Column + Default + QueryFragment<Pg> + NonAggregate,
+{
+    type Ret = JsonbPredicate<C>;
+
+    fn into_filter(self) -> Option<Self::Ret> {
+        let mut conditions = Vec::new();
+        if let Some(Json(value)) = self.contains {
+            conditions.push(JsonbCondition::Contains(value));
+        }
+        if let Some(key) = self.has_key {
+            conditions.push(JsonbCondition::HasKey(key));
+        }
+        for filter in self.path.unwrap_or_default() {
+            let path = filter.path;
+            if let Some(exists) = filter.exists {
+                conditions.push(JsonbCondition::PathExists(path.clone(), exists));
+            }
+            if let Some(eq) = filter.eq {
+                conditions.push(JsonbCondition::PathEq(path.clone(), eq));
+            }
+            let comparisons = [
+                (" > ", filter.gt),
+                (" >= ", filter.gte),
+                (" < ", filter.lt),
+                (" <= ", filter.lte),
+            ];
+            for &(op, value) in &comparisons {
+                if let Some(value) = value {
+                    conditions.push(JsonbCondition::PathCompare(path.clone(), op, value));
+                }
+            }
+        }
+        if conditions.is_empty() {
+            None
+        } else {
+            Some(JsonbPredicate {
+                column: C::default(),
+                conditions,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JsonbCondition {
+    Contains(JsonValue),
+    HasKey(String),
+    PathExists(Vec<String>, bool),
+    PathEq(Vec<String>, String),
+    /// Only matches numbers, the operator is one of a fixed set
+    PathCompare(Vec<String>, &'static str, f64),
+}
+
+/// Conjunction of JSONB conditions on a single column
+#[derive(Debug, Clone)]
+pub struct JsonbPredicate<C> {
+    column: C,
+    conditions: Vec<JsonbCondition>,
+}
+
+impl<C> Expression for JsonbPredicate<C> {
+    type SqlType = Bool;
+}
+
+impl<C> NonAggregate for JsonbPredicate<C> {}
+
+impl<C, QS> AppearsOnTable<QS> for JsonbPredicate<C> where C: AppearsOnTable<QS> {}
+
+impl<C, QS> SelectableExpression<QS> for JsonbPredicate<C> where C: SelectableExpression<QS> {}
+
+impl<C> QueryId for JsonbPredicate<C> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<C> QueryFragment<Pg> for JsonbPredicate<C>
+where
+    C: QueryFragment<Pg>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        out.push_sql("(");
+        for (i, condition) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(" AND ");
+            }
+            match condition {
+                JsonbCondition::Contains(value) => {
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" @> ");
+                    out.push_bind_param::<Jsonb, _>(value)?;
+                }
+                JsonbCondition::HasKey(key) => {
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" ? ");
+                    out.push_bind_param::<Text, _>(key)?;
+                }
+                JsonbCondition::PathExists(path, exists) => {
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" #> ");
+                    out.push_bind_param::<Array<Text>, _>(path)?;
+                    out.push_sql(if *exists { " IS NOT NULL" } else { " IS NULL" });
+                }
+                JsonbCondition::PathEq(path, value) => {
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" #>> ");
+                    out.push_bind_param::<Array<Text>, _>(path)?;
+                    out.push_sql(" = ");
+                    out.push_bind_param::<Text, _>(value)?;
+                }
+                JsonbCondition::PathCompare(path, op, value) => {
+                    out.push_sql("CASE WHEN jsonb_typeof(");
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" #> ");
+                    out.push_bind_param::<Array<Text>, _>(path)?;
+                    out.push_sql(") = 'number' THEN (");
+                    self.column.walk_ast(out.reborrow())?;
+                    out.push_sql(" #>> ");
+                    out.push_bind_param::<Array<Text>, _>(path)?;
+                    out.push_sql(")::float8 END");
+                    out.push_sql(op);
+                    out.push_bind_param::<Double, _>(value)?;
+                }
+            }
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::vector_styles;
+    use diesel::{debug_query, QueryDsl};
+    use serde_json::json;
+    use std::fmt::Debug;
+
+    fn input(value: &str) -> InputValue<WundergraphScalarValue> {
+        serde_json::from_str(value).unwrap()
+    }
+
+    fn filter(value: &str) -> Option<JsonbFilter<vector_styles::style>> {
+        match input(value) {
+            InputValue::Object(fields) => JsonbFilter::from_inner_input_value(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.item.as_str(), &v.item))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn sql(filter: JsonbFilter<vector_styles::style>) -> String {
+        let query = vector_styles::table
+            .select(vector_styles::id)
+            .filter(filter.into_filter().unwrap());
+        debug_query::<Pg, _>(&query).to_string()
+    }
+
+    #[test]
+    fn filter_sql() {
+        let filter = filter(
+            r#"{
+                "contains": {"stroke": "it's"},
+                "hasKey": "fill\"; DROP TABLE vector_styles; --",
+                "path": [
+                    {"path": ["a b", "0", "it's"], "exists": true, "eq": "x", "gt": 1.5, "lte": 3},
+                    {"path": ["c"], "exists": false}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let sql = sql(filter);
+        let expected = format!(
+            "WHERE ({c} @> $1 AND {c} ? $2 AND {c} #> $3 IS NOT NULL AND {c} #>> $4 = $5 \
+             AND CASE WHEN jsonb_typeof({c} #> $6) = 'number' THEN ({c} #>> $7)::float8 END > $8 \
+             AND CASE WHEN jsonb_typeof({c} #> $9) = 'number' THEN ({c} #>> $10)::float8 END <= $11 \
+             AND {c} #> $12 IS NULL)",
+            c = "\"vector_styles\".\"style\""
+        );
+        assert!(sql.contains(&expected), "{}", sql);
+
+        // Keys, paths and values are only ever bound, in the order they appear
+        let path = vec!["a b", "0", "it's"];
+        let contains = json!({"stroke": "it's"});
+        let binds: Vec<&dyn Debug> = vec![
+            &contains,
+            &"fill\"; DROP TABLE vector_styles; --",
+            &path,
+            &path,
+            &"x",
+            &path,
+            &path,
+            &1.5,
+            &path,
+            &path,
+            &3.0,
+            &["c"],
+        ];
+        assert!(sql.ends_with(&format!("binds: {:?}", binds)), "{}", sql);
+    }
+
+    #[test]
+    fn empty_filters() {
+        assert!(filter("{}").unwrap().into_filter().is_none());
+        assert!(filter(r#"{"path": [{"path": ["a"]}]}"#)
+            .unwrap()
+            .into_filter()
+            .is_none());
+        // Conditions without `path` are rejected by query validation, the
+        // derived `JsonPathFilter` input object expects it
+        assert!(filter(r#"{"hasKey": 1}"#).is_none());
+    }
+}
@@ -0,0 +1,260 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, ToSql};
+use diesel::sql_types::{Jsonb, Nullable};
+use juniper::parser::{ParseError, ScalarToken, Token};
+use juniper::{InputValue, LookAheadValue, ParseScalarResult, Value};
+use serde_json::Value as JsonValue;
+use std::io::Write;
+use wundergraph::juniper_ext::{FromLookAheadValue, Nameable};
+use wundergraph::query_builder::selection::filter::{AsColumnFilter, FilterOption, FilterValue};
+use wundergraph::query_builder::types::{PlaceHolder, WundergraphValue};
+use wundergraph::scalar::WundergraphScalarValue;
+
+mod filter;
+
+pub use self::filter::{JsonPathFilter, JsonbFilter, JsonbPredicate};
+
+/// Arbitrary JSON document stored in a `JSONB` column
+///
+/// Exposed as the `Json` scalar: objects, lists and scalars are written and
+/// read as plain GraphQL values, no string encoding involved.
+#[derive(Debug, Clone, PartialEq, AsExpression, FromSqlRow)]
+#[sql_type = "Jsonb"]
+pub struct Json(pub JsonValue);
+
+impl From<JsonValue> for Json {
+    fn from(v: JsonValue) -> Self {
+        Json(v)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for Json {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<'_, W, Pg>) -> serialize::Result {
+        ToSql::<Jsonb, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Jsonb, Pg> for Json {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        <JsonValue as FromSql<Jsonb, Pg>>::from_sql(bytes).map(Json)
+    }
+}
+
+impl WundergraphValue for Json {
+    type PlaceHolder = PlaceHolder<Self>;
+    type SqlType = Nullable<Jsonb>;
+}
+
+impl<C> FilterValue<C> for Json {
+    type RawValue = Self;
+    type AdditionalFilter = JsonbFilter<C>;
+}
+
+impl<C, DB, Ctx> AsColumnFilter<C, DB, Ctx> for Json {
+    type Filter = FilterOption<Self, C>;
+}
+
+impl Nameable for Json {
+    fn name() -> String {
+        String::from("Json")
+    }
+}
+
+impl FromLookAheadValue for Json {
+    fn from_look_ahead(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<Self> {
+        look_ahead_to_json(v).map(Json)
+    }
+}
+
+graphql_scalar!(Json as "Json" where Scalar = WundergraphScalarValue {
+    description: "Arbitrary JSON value"
+
+    resolve(&self) -> Value {
+        json_to_value(&self.0)
+    }
+
+    from_input_value(v: &InputValue) -> Option<Json> {
+        input_to_json(v).map(Json)
+    }
+
+    from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, WundergraphScalarValue> {
+        match value {
+            ScalarToken::String(s) => Ok(WundergraphScalarValue::from(s.to_owned())),
+            ScalarToken::Int(i) => {
+                parse_int(i).ok_or(ParseError::UnexpectedToken(Token::Scalar(value)))
+            }
+            ScalarToken::Float(f) => f
+                .parse::<f64>()
+                .map(WundergraphScalarValue::from)
+                .map_err(|_| ParseError::UnexpectedToken(Token::Scalar(value))),
+        }
+    }
+});
+
+/// GraphQL value of a JSON document
+///
+/// Integers are kept as `Int` or, out of its range, `BigInt`. Integers
+/// above `i64::MAX` become floats, the way wundergraph reads them from
+/// variables.
+pub fn json_to_value(v: &JsonValue) -> Value<WundergraphScalarValue> {
+    match v {
+        JsonValue::Null => Value::null(),
+        JsonValue::Bool(b) => Value::scalar(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) if i >= i64::from(i32::MIN) && i <= i64::from(i32::MAX) => {
+                Value::scalar(i as i32)
+            }
+            Some(i) => Value::scalar(i),
+            _ => Value::scalar(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => Value::scalar(s.clone()),
+        JsonValue::Array(values) => Value::list(values.iter().map(json_to_value).collect()),
+        JsonValue::Object(fields) => Value::object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.as_str(), json_to_value(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Integer literal as the smallest integer scalar holding it, or a float
+/// beyond `i64`, the way wundergraph reads integer variables
+fn parse_int(literal: &str) -> Option<WundergraphScalarValue> {
+    if let Ok(i) = literal.parse::<i32>() {
+        Some(WundergraphScalarValue::Int(i))
+    } else if let Ok(i) = literal.parse::<i64>() {
+        Some(WundergraphScalarValue::BigInt(i))
+    } else {
+        literal
+            .parse::<f64>()
+            .ok()
+            .map(WundergraphScalarValue::Double)
+    }
+}
+
+fn scalar_to_json(s: &WundergraphScalarValue) -> Option<JsonValue> {
+    match *s {
+        WundergraphScalarValue::SmallInt(i) => Some(JsonValue::from(i)),
+        WundergraphScalarValue::Int(i) => Some(JsonValue::from(i)),
+        WundergraphScalarValue::BigInt(i) => Some(JsonValue::from(i)),
+        WundergraphScalarValue::Float(f) => {
+            serde_json::Number::from_f64(f64::from(f)).map(JsonValue::Number)
+        }
+        WundergraphScalarValue::Double(f) => serde_json::Number::from_f64(f).map(JsonValue::Number),
+        WundergraphScalarValue::String(ref s) => Some(JsonValue::String(s.clone())),
+        WundergraphScalarValue::Boolean(b) => Some(JsonValue::Bool(b)),
+    }
+}
+
+pub fn input_to_json(v: &InputValue<WundergraphScalarValue>) -> Option<JsonValue> {
+    Some(match v {
+        InputValue::Null => JsonValue::Null,
+        InputValue::Scalar(s) => scalar_to_json(s)?,
+        InputValue::Enum(e) => JsonValue::String(e.clone()),
+        InputValue::Variable(_) => return None,
+        InputValue::List(values) => JsonValue::Array(
+            values
+                .iter()
+                .map(|v| input_to_json(&v.item))
+                .collect::<Option<_>>()?,
+        ),
+        InputValue::Object(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(k, v)| input_to_json(&v.item).map(|v| (k.item.clone(), v)))
+                .collect::<Option<_>>()?,
+        ),
+    })
+}
+
+pub fn look_ahead_to_json(v: &LookAheadValue<'_, WundergraphScalarValue>) -> Option<JsonValue> {
+    Some(match v {
+        LookAheadValue::Null => JsonValue::Null,
+        LookAheadValue::Scalar(s) => scalar_to_json(s)?,
+        LookAheadValue::Enum(e) => JsonValue::String((*e).to_owned()),
+        LookAheadValue::List(values) => JsonValue::Array(
+            values
+                .iter()
+                .map(look_ahead_to_json)
+                .collect::<Option<_>>()?,
+        ),
+        LookAheadValue::Object(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(k, v)| look_ahead_to_json(v).map(|v| ((*k).to_owned(), v)))
+                .collect::<Option<_>>()?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use juniper::ParseScalarValue;
+    use serde_json::json;
+
+    fn round_trip(doc: JsonValue) {
+        let value = json_to_value(&doc);
+        assert_eq!(serde_json::to_value(&value).unwrap(), doc);
+        let input = serde_json::from_value::<InputValue<WundergraphScalarValue>>(doc.clone());
+        assert_eq!(input_to_json(&input.unwrap()), Some(doc));
+    }
+
+    #[test]
+    fn integers() {
+        round_trip(json!(0));
+        round_trip(json!(-7));
+        round_trip(json!(i32::MAX));
+        round_trip(json!(i64::from(i32::MAX) + 1));
+        round_trip(json!(9_007_199_254_740_993_i64));
+        round_trip(json!(i64::MAX));
+        assert_eq!(json_to_value(&json!(i64::MIN)), Value::scalar(i64::MIN));
+    }
+
+    #[test]
+    fn documents() {
+        round_trip(json!(null));
+        round_trip(json!({
+            "id": 9_007_199_254_740_993_i64,
+            "name": "track",
+            "score": 0.25,
+            "visible": true,
+            "tags": ["a", 1, null, { "nested": [2_147_483_648_i64] }],
+        }));
+    }
+
+    #[test]
+    fn big_integers_beyond_i64_are_floats() {
+        assert_eq!(
+            json_to_value(&json!(u64::MAX)),
+            Value::scalar(u64::MAX as f64)
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let parse = |token| <Json as ParseScalarValue<WundergraphScalarValue>>::from_str(token);
+        assert_eq!(
+            parse(ScalarToken::Int("42")),
+            Ok(WundergraphScalarValue::Int(42))
+        );
+        assert_eq!(
+            parse(ScalarToken::Int("9007199254740993")),
+            Ok(WundergraphScalarValue::BigInt(9_007_199_254_740_993))
+        );
+        assert_eq!(
+            parse(ScalarToken::Int("-9223372036854775808")),
+            Ok(WundergraphScalarValue::BigInt(i64::MIN))
+        );
+        assert_eq!(
+            parse(ScalarToken::Float("1.5")),
+            Ok(WundergraphScalarValue::Double(1.5))
+        );
+        assert_eq!(
+            scalar_to_json(&WundergraphScalarValue::BigInt(9_007_199_254_740_993)),
+            Some(json!(9_007_199_254_740_993_i64))
+        );
+    }
+}
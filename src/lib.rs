@@ -4,49 +4,81 @@ extern crate diesel;
 #[macro_use]
 extern crate juniper;
 
-use diesel::backend::Backend;
-use diesel::deserialize::{self, FromSql};
+#[macro_use]
+extern crate diesel_migrations;
+
 use diesel::r2d2::{ConnectionManager, PooledConnection};
-use diesel::serialize::{self, ToSql};
 use diesel::sql_types::SmallInt;
-use std::io::Write;
 use wundergraph::query_builder::types::{HasMany, HasOne, WundergraphValue};
 
+#[macro_use]
+mod macros;
+
+pub mod bounding_box;
+pub mod colormap;
+pub mod config;
+pub mod db;
+pub mod frame_lookup;
+pub mod frame_time;
 pub mod generated;
+pub mod graphql;
+pub mod health;
+pub mod json;
+pub mod links;
+pub mod logging;
+pub mod media;
+pub mod movie_content;
+pub mod render;
+pub mod timeline;
 // mod schema;
 
-#[derive(
-    Debug, Copy, Clone, AsExpression, FromSqlRow, GraphQLEnum, WundergraphValue, Eq, PartialEq, Hash,
-)]
-#[sql_type = "SmallInt"]
-pub enum Episode {
-    NEWHOPE = 1,
-    EMPIRE = 2,
-    JEDI = 3,
+small_int_enum! {
+    pub enum Episode {
+        NEWHOPE = 1,
+        EMPIRE = 2,
+        JEDI = 3,
+    }
 }
 
-impl<DB> ToSql<SmallInt, DB> for Episode
-where
-    DB: Backend,
-    i16: ToSql<SmallInt, DB>,
-{
-    fn to_sql<W: Write>(&self, out: &mut serialize::Output<'_, W, DB>) -> serialize::Result {
-        (*self as i16).to_sql(out)
+small_int_enum! {
+    /// Pixel format of the frames of a `ColorMovie`
+    pub enum ColorFormat {
+        GRAY8 = 1,
+        GRAY16 = 2,
+        RGB = 3,
+        RGBA = 4,
+        FLOAT32 = 5,
     }
 }
 
-impl<DB> FromSql<SmallInt, DB> for Episode
-where
-    DB: Backend,
-    i16: FromSql<SmallInt, DB>,
-{
-    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
-        let value = i16::from_sql(bytes)?;
-        Ok(match value {
-            1 => Episode::NEWHOPE,
-            2 => Episode::EMPIRE,
-            3 => Episode::JEDI,
-            _ => unreachable!(),
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::deserialize::FromSql;
+    use diesel::pg::Pg;
+
+    fn decode(value: i16) -> diesel::deserialize::Result<ColorFormat> {
+        <ColorFormat as FromSql<SmallInt, Pg>>::from_sql(Some(&value.to_be_bytes()))
+    }
+
+    #[test]
+    fn small_int_enum_values() {
+        assert_eq!(decode(1).unwrap(), ColorFormat::GRAY8);
+        assert_eq!(decode(5).unwrap(), ColorFormat::FLOAT32);
+        assert_eq!(ColorFormat::RGB as i16, 3);
+        assert_eq!(Episode::JEDI as i16, 3);
+    }
+
+    #[test]
+    fn small_int_enum_unknown_values() {
+        assert_eq!(
+            decode(0).unwrap_err().to_string(),
+            "Unknown ColorFormat value 0"
+        );
+        assert_eq!(
+            decode(-1).unwrap_err().to_string(),
+            "Unknown ColorFormat value -1"
+        );
+        assert!(<Episode as FromSql<SmallInt, Pg>>::from_sql(None).is_err());
     }
 }
@@ -0,0 +1,492 @@
+use crate::generated::*;
+use diesel::associations::HasTable;
+use diesel::expression::{AppearsOnTable, Expression, NonAggregate, SelectableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::query_source::{Column, QuerySource};
+use diesel::result::QueryResult;
+use diesel::sql_types::{Array, Bool, Int4};
+use std::marker::PhantomData;
+
+/// Reference from a parent entity to the rows of the table `K` belongs to,
+/// going through a join table
+///
+/// `P` and `T` are the join table columns referencing the parent and the
+/// target, `K` is the primary key of the target. Used as the foreign key of
+/// a `HasMany`, it acts as a column of the target table holding the ids of
+/// its parents:
+///
+/// ```sql
+/// unnest(ARRAY(SELECT P FROM join_table WHERE T = K))
+/// ```
+///
+/// so a movie can list its tags with `HasMany<Tag, Link<movies_tags::movie_id,
+/// movies_tags::tag_id, tags::id>>`, without exposing the `MoviesTag` rows.
+#[derive(Debug, Clone)]
+pub struct Link<P, T, K> {
+    parents: Option<Vec<i32>>,
+    p: PhantomData<(P, T, K)>,
+}
+
+impl<P, T, K> Link<P, T, K> {
+    /// Only yields the given parent ids, used when loading the association
+    pub fn among(parents: &[Option<i32>]) -> Self {
+        Link {
+            parents: Some(parents.iter().filter_map(|p| *p).collect()),
+            p: PhantomData,
+        }
+    }
+}
+
+impl<P, T, K> Default for Link<P, T, K> {
+    fn default() -> Self {
+        Link {
+            parents: None,
+            p: PhantomData,
+        }
+    }
+}
+
+impl<P, T, K> Expression for Link<P, T, K> {
+    type SqlType = Int4;
+}
+
+impl<P, T, K> Column for Link<P, T, K>
+where
+    P: Column,
+    T: Column<Table = P::Table>,
+    K: Column,
+{
+    type Table = K::Table;
+
+    const NAME: &'static str = K::NAME;
+}
+
+impl<P, T, K> NonAggregate for Link<P, T, K> {}
+
+impl<P, T, K, QS> AppearsOnTable<QS> for Link<P, T, K> where K: AppearsOnTable<QS> {}
+
+impl<P, T, K, QS> SelectableExpression<QS> for Link<P, T, K> where K: SelectableExpression<QS> {}
+
+impl<P, T, K> QueryId for Link<P, T, K> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<P, T, K> QueryFragment<Pg> for Link<P, T, K>
+where
+    P: Column + Default + QueryFragment<Pg>,
+    T: Column + Default + QueryFragment<Pg>,
+    K: Default + QueryFragment<Pg>,
+    P::Table: HasTable<Table = P::Table>,
+    <P::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        out.push_sql("unnest(ARRAY(SELECT ");
+        P::default().walk_ast(out.reborrow())?;
+        out.push_sql(" FROM ");
+        P::Table::table().from_clause().walk_ast(out.reborrow())?;
+        out.push_sql(" WHERE ");
+        T::default().walk_ast(out.reborrow())?;
+        out.push_sql(" = ");
+        K::default().walk_ast(out.reborrow())?;
+        if let Some(ref parents) = self.parents {
+            out.push_sql(" AND ");
+            P::default().walk_ast(out.reborrow())?;
+            out.push_sql(" = ANY(");
+            out.push_bind_param::<Array<Int4>, _>(parents)?;
+            out.push_sql(")");
+        }
+        out.push_sql("))");
+        Ok(())
+    }
+}
+
+/// `K IN (SELECT T FROM join_table WHERE P = ANY(parents))`, restricts the
+/// targets to the ones linked to the loaded parents before expanding them
+/// with [`Link`](struct.Link.html)
+#[derive(Debug, Clone)]
+pub struct Linked<P, T, K> {
+    parents: Vec<i32>,
+    p: PhantomData<(P, T, K)>,
+}
+
+impl<P, T, K> Linked<P, T, K> {
+    pub fn to(parents: &[Option<i32>]) -> Self {
+        Linked {
+            parents: parents.iter().filter_map(|p| *p).collect(),
+            p: PhantomData,
+        }
+    }
+}
+
+impl<P, T, K> Expression for Linked<P, T, K> {
+    type SqlType = Bool;
+}
+
+impl<P, T, K> NonAggregate for Linked<P, T, K> {}
+
+impl<P, T, K, QS> AppearsOnTable<QS> for Linked<P, T, K> where K: AppearsOnTable<QS> {}
+
+impl<P, T, K, QS> SelectableExpression<QS> for Linked<P, T, K> where K: SelectableExpression<QS> {}
+
+impl<P, T, K> QueryId for Linked<P, T, K> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<P, T, K> QueryFragment<Pg> for Linked<P, T, K>
+where
+    P: Column + Default + QueryFragment<Pg>,
+    T: Column + Default + QueryFragment<Pg>,
+    K: Default + QueryFragment<Pg>,
+    P::Table: HasTable<Table = P::Table>,
+    <P::Table as QuerySource>::FromClause: QueryFragment<Pg>,
+{
+    fn walk_ast(&self, mut out: AstPass<'_, Pg>) -> QueryResult<()> {
+        K::default().walk_ast(out.reborrow())?;
+        out.push_sql(" IN (SELECT ");
+        T::default().walk_ast(out.reborrow())?;
+        out.push_sql(" FROM ");
+        P::Table::table().from_clause().walk_ast(out.reborrow())?;
+        out.push_sql(" WHERE ");
+        P::default().walk_ast(out.reborrow())?;
+        out.push_sql(" = ANY(");
+        out.push_bind_param::<Array<Int4>, _>(&self.parents)?;
+        out.push_sql("))");
+        Ok(())
+    }
+}
+
+/// Implement `WundergraphBelongsTo` for an entity reached through a join
+/// table, which the entity derive only does for `HasOne` fields
+///
+/// Targets and their parent ids are loaded in a single query, a target
+/// linked to several loaded parents is returned once per parent.
+macro_rules! belongs_to_through {
+    ($($target:ty => $parent:ident through $join:ident($parent_fk:ident, $target_fk:ident) on $table:ident::$key:ident,)+) => {
+        $(
+            impl<Ctx> wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                $parent::table,
+                Pg,
+                Ctx,
+                Link<$join::$parent_fk, $join::$target_fk, $table::$key>,
+            > for $target
+            where
+                Ctx: wundergraph::WundergraphContext + 'static,
+                <Ctx as wundergraph::WundergraphContext>::Connection:
+                    diesel::Connection<Backend = Pg>,
+            {
+                type Key = i32;
+
+                fn resolve(
+                    global_args: &[juniper::LookAheadArgument<wundergraph::scalar::WundergraphScalarValue>],
+                    look_ahead: &juniper::LookAheadSelection<'_, wundergraph::scalar::WundergraphScalarValue>,
+                    selection: Option<&'_ [juniper::Selection<'_, wundergraph::scalar::WundergraphScalarValue>]>,
+                    keys: &[Option<i32>],
+                    executor: &juniper::Executor<'_, Ctx, wundergraph::scalar::WundergraphScalarValue>,
+                ) -> wundergraph::error::Result<
+                    std::collections::HashMap<
+                        Option<i32>,
+                        Vec<juniper::Value<wundergraph::scalar::WundergraphScalarValue>>,
+                    >,
+                > {
+                    use diesel::{NullableExpressionMethods, QueryDsl, RunQueryDsl};
+                    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler};
+
+                    type Fk = Link<$join::$parent_fk, $join::$target_fk, $table::$key>;
+
+                    let conn = executor.context().get_connection();
+                    let query = <_ as QueryDsl>::filter(
+                        <BoxedQuery<Self, Pg, Ctx> as QueryDsl>::select(
+                            <Self as LoadingHandler<Pg, Ctx>>::build_query(global_args, look_ahead)?,
+                            (
+                                Fk::among(keys).nullable(),
+                                <Self as LoadingHandler<Pg, Ctx>>::get_select(look_ahead)?,
+                            ),
+                        ),
+                        Linked::<$join::$parent_fk, $join::$target_fk, $table::$key>::to(keys),
+                    );
+                    <Self as wundergraph::query_builder::selection::fields::WundergraphBelongsTo<
+                        $parent::table,
+                        Pg,
+                        Ctx,
+                        Fk,
+                    >>::build_response(query.load(conn)?, global_args, look_ahead, selection, executor)
+                }
+            }
+        )+
+    };
+}
+
+belongs_to_through! {
+    Tag => movies through movies_tags(movie_id, tag_id) on tags::id,
+    TagsValue => images through images_tags_values(image_id, tags_value_id) on tags_values::id,
+    Movie => cinemas through cinemas_movies(cinema_id, movie_id) on movies::id,
+    Colormap => color_movies through color_movie_colormap(color_movie_id, colormap_id) on colormaps::id,
+    VectorStyle => vector_movies through vector_styles_vector_movies(vector_movie_id, vector_style_id) on vector_styles::id,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::{AnsiTransactionManager, SimpleConnection};
+    use diesel::deserialize::{Queryable, QueryableByName};
+    use diesel::query_builder::{AsQuery, QueryFragment};
+    use diesel::sql_types::HasSqlType;
+    use diesel::{debug_query, Connection, ConnectionResult, PgConnection, QueryDsl, RunQueryDsl};
+    use diesel::{ExpressionMethods, NullableExpressionMethods};
+    use juniper::{LookAheadSelection, Variables};
+    use std::cell::RefCell;
+    use wundergraph::error::Result as WunderResult;
+    use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler, QueryModifier};
+    use wundergraph::scalar::WundergraphScalarValue;
+    use wundergraph::WundergraphContext;
+
+    type MovieTags = Link<movies_tags::movie_id, movies_tags::tag_id, tags::id>;
+
+    #[test]
+    fn link_sql() {
+        let keys = [Some(1), None, Some(2)];
+        let query = tags::table
+            .select((MovieTags::among(&keys).nullable(), tags::name))
+            .filter(Linked::<movies_tags::movie_id, movies_tags::tag_id, tags::id>::to(&keys));
+        let sql = debug_query::<Pg, _>(&query).to_string();
+        assert_eq!(
+            sql,
+            "SELECT unnest(ARRAY(SELECT \"movies_tags\".\"movie_id\" FROM \"movies_tags\" \
+             WHERE \"movies_tags\".\"tag_id\" = \"tags\".\"id\" \
+             AND \"movies_tags\".\"movie_id\" = ANY($1))), \"tags\".\"name\" FROM \"tags\" \
+             WHERE \"tags\".\"id\" IN (SELECT \"movies_tags\".\"tag_id\" FROM \"movies_tags\" \
+             WHERE \"movies_tags\".\"movie_id\" = ANY($2)) -- binds: [[1, 2], [1, 2]]"
+        );
+    }
+
+    /// `PgConnection` keeping the SQL of the queries it runs
+    struct Recording {
+        conn: PgConnection,
+        queries: RefCell<Vec<String>>,
+    }
+
+    impl SimpleConnection for Recording {
+        fn batch_execute(&self, query: &str) -> QueryResult<()> {
+            self.conn.batch_execute(query)
+        }
+    }
+
+    impl Connection for Recording {
+        type Backend = Pg;
+        type TransactionManager = AnsiTransactionManager;
+
+        fn establish(database_url: &str) -> ConnectionResult<Self> {
+            Ok(Recording {
+                conn: PgConnection::establish(database_url)?,
+                queries: RefCell::default(),
+            })
+        }
+
+        fn execute(&self, query: &str) -> QueryResult<usize> {
+            self.conn.execute(query)
+        }
+
+        fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+        where
+            T: AsQuery,
+            T::Query: QueryFragment<Pg> + QueryId,
+            Pg: HasSqlType<T::SqlType>,
+            U: Queryable<T::SqlType, Pg>,
+        {
+            let query = source.as_query();
+            let sql = debug_query::<Pg, _>(&query).to_string();
+            self.queries.borrow_mut().push(sql);
+            self.conn.query_by_index(query)
+        }
+
+        fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+        where
+            T: QueryFragment<Pg> + QueryId,
+            U: QueryableByName<Pg>,
+        {
+            self.conn.query_by_name(source)
+        }
+
+        fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+        where
+            T: QueryFragment<Pg> + QueryId,
+        {
+            self.conn.execute_returning_count(source)
+        }
+
+        fn transaction_manager(&self) -> &Self::TransactionManager {
+            self.conn.transaction_manager()
+        }
+    }
+
+    struct Context(Recording);
+
+    impl WundergraphContext for Context {
+        type Connection = Recording;
+
+        fn get_connection(&self) -> &Recording {
+            &self.0
+        }
+    }
+
+    impl<T> QueryModifier<T, Pg> for Context
+    where
+        T: LoadingHandler<Pg, Self>,
+    {
+        fn modify_query<'a>(
+            &self,
+            _select: &LookAheadSelection<'_, WundergraphScalarValue>,
+            query: BoxedQuery<'a, T, Pg, Self>,
+        ) -> WunderResult<BoxedQuery<'a, T, Pg, Self>> {
+            Ok(query)
+        }
+    }
+
+    fn insert_ids<T>(conn: &PgConnection, query: T) -> Vec<i32>
+    where
+        T: RunQueryDsl<PgConnection> + diesel::query_dsl::LoadQuery<PgConnection, i32>,
+    {
+        query.get_results(conn).unwrap()
+    }
+
+    /// Run with `TEST_DATABASE_URL` pointing to a migrated database, changes
+    /// are rolled back
+    #[test]
+    #[ignore]
+    fn links_resolve_in_one_query() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let ctx = Context(Recording::establish(&url).unwrap());
+        let conn = &ctx.0.conn;
+        conn.begin_test_transaction().unwrap();
+
+        let movies = insert_ids(
+            conn,
+            diesel::insert_into(movies::table)
+                .values(&vec![
+                    (movies::identifier.eq("link-a"), movies::name.eq("A")),
+                    (movies::identifier.eq("link-b"), movies::name.eq("B")),
+                    (movies::identifier.eq("link-c"), movies::name.eq("C")),
+                ])
+                .returning(movies::id),
+        );
+        let tags = insert_ids(
+            conn,
+            diesel::insert_into(tags::table)
+                .values(&vec![tags::name.eq("link-x"), tags::name.eq("link-y")])
+                .returning(tags::id),
+        );
+        // A: x, y; B: y; C: none
+        diesel::insert_into(movies_tags::table)
+            .values(&vec![
+                (
+                    movies_tags::movie_id.eq(movies[0]),
+                    movies_tags::tag_id.eq(tags[0]),
+                ),
+                (
+                    movies_tags::movie_id.eq(movies[0]),
+                    movies_tags::tag_id.eq(tags[1]),
+                ),
+                (
+                    movies_tags::movie_id.eq(movies[1]),
+                    movies_tags::tag_id.eq(tags[1]),
+                ),
+            ])
+            .execute(conn)
+            .unwrap();
+        let cinema = insert_ids(
+            conn,
+            diesel::insert_into(cinemas::table)
+                .values(cinemas::name.eq("link-cinema"))
+                .returning(cinemas::id),
+        )[0];
+        diesel::insert_into(cinemas_movies::table)
+            .values(&vec![
+                (
+                    cinemas_movies::cinema_id.eq(cinema),
+                    cinemas_movies::movie_id.eq(movies[0]),
+                ),
+                (
+                    cinemas_movies::cinema_id.eq(cinema),
+                    cinemas_movies::movie_id.eq(movies[2]),
+                ),
+            ])
+            .execute(conn)
+            .unwrap();
+        ctx.0.queries.borrow_mut().clear();
+
+        let schema =
+            juniper::RootNode::new(Query::<Context>::default(), Mutation::<Context>::default());
+        let query = r#"{
+            Movies(filter: {identifier: {like: "link-%"}}) {
+                name
+                tags { name }
+            }
+        }"#;
+        let (result, errors) =
+            juniper::execute(query, None, &schema, &Variables::new(), &ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut names = serde_json::to_value(&result).unwrap()["Movies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| {
+                let mut tags = m["tags"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| t["name"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+                tags.sort();
+                (m["name"].as_str().unwrap().to_owned(), tags)
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                (
+                    "A".to_owned(),
+                    vec!["link-x".to_owned(), "link-y".to_owned()]
+                ),
+                ("B".to_owned(), vec!["link-y".to_owned()]),
+                ("C".to_owned(), vec![]),
+            ]
+        );
+        let queries = ctx.0.queries.borrow();
+        assert_eq!(queries.len(), 2, "{:#?}", queries);
+        assert!(
+            queries[1].contains("FROM \"movies_tags\""),
+            "{}",
+            queries[1]
+        );
+        drop(queries);
+
+        ctx.0.queries.borrow_mut().clear();
+        let query = format!(
+            "{{ Cinema(primaryKey: {{id: {}}}) {{ movies {{ name }} }} }}",
+            cinema
+        );
+        let (result, errors) =
+            juniper::execute(&query, None, &schema, &Variables::new(), &ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut names = serde_json::to_value(&result).unwrap()["Cinema"]["movies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["A", "C"]);
+        assert_eq!(
+            ctx.0.queries.borrow().len(),
+            2,
+            "{:#?}",
+            ctx.0.queries.borrow()
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::{LogFormat, LoggingConfig};
+use env_logger::{Builder, Env};
+use log::{info, Record};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Target of the per request summary line
+pub const REQUEST_TARGET: &str = "test_wundergraph::request";
+
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+static REQUEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Fields of the request summary being logged on this thread, picked up
+    /// by the JSON format as the logger formats records on the calling thread
+    static REQUEST_FIELDS: RefCell<Option<Map<String, Value>>> = const { RefCell::new(None) };
+}
+
+/// Install the global logger
+///
+/// `RUST_LOG` takes precedence over `logging.filter` when set.
+pub fn init(config: &LoggingConfig) {
+    let mut builder = Builder::from_env(Env::default().default_filter_or(config.filter.as_str()));
+    if config.format == LogFormat::Json {
+        JSON_FORMAT.store(true, Ordering::Relaxed);
+        builder.format(|buf, record| {
+            let fields = if record.target() == REQUEST_TARGET {
+                REQUEST_FIELDS.with(|fields| fields.borrow_mut().take())
+            } else {
+                None
+            };
+            writeln!(buf, "{}", json_record(record, fields))
+        });
+    }
+    builder.init();
+}
+
+/// One line of the JSON format, the fields of a request summary are put at
+/// the top level instead of a `message`
+fn json_record(record: &Record<'_>, fields: Option<Map<String, Value>>) -> Value {
+    let mut entry = Map::new();
+    entry.insert("timestamp".into(), chrono::Utc::now().to_rfc3339().into());
+    entry.insert("level".into(), record.level().to_string().into());
+    entry.insert("target".into(), record.target().into());
+    match fields {
+        Some(fields) => entry.extend(fields),
+        None => {
+            entry.insert("message".into(), record.args().to_string().into());
+        }
+    }
+    Value::Object(entry)
+}
+
+/// Generate an id for requests coming without a `X-Request-Id` header
+pub fn next_request_id() -> String {
+    format!(
+        "{:x}-{:x}",
+        process::id(),
+        REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Summary of an executed GraphQL request
+#[derive(Debug, Serialize)]
+pub struct RequestLog<'a> {
+    pub request_id: &'a str,
+    pub operation_name: Option<&'a str>,
+    pub duration_ms: f64,
+    pub errors: usize,
+}
+
+pub fn log_request(entry: &RequestLog<'_>) {
+    if JSON_FORMAT.load(Ordering::Relaxed) {
+        if let Ok(Value::Object(fields)) = serde_json::to_value(entry) {
+            REQUEST_FIELDS.with(|f| *f.borrow_mut() = Some(fields));
+            info!(target: REQUEST_TARGET, "request {}", entry.request_id);
+            // Not taken when the record is filtered out
+            REQUEST_FIELDS.with(|f| f.borrow_mut().take());
+        }
+    } else {
+        info!(
+            target: REQUEST_TARGET,
+            "request_id={} operation={} duration_ms={:.3} errors={}",
+            entry.request_id,
+            entry.operation_name.unwrap_or("-"),
+            entry.duration_ms,
+            entry.errors
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn request_fields_at_top_level() {
+        let entry = RequestLog {
+            request_id: "1-a",
+            operation_name: Some("Heros"),
+            duration_ms: 1.5,
+            errors: 2,
+        };
+        let fields = match serde_json::to_value(&entry).unwrap() {
+            Value::Object(fields) => fields,
+            v => panic!("not an object: {}", v),
+        };
+        let line = json_record(
+            &Record::builder()
+                .level(Level::Info)
+                .target(REQUEST_TARGET)
+                .args(format_args!("request 1-a"))
+                .build(),
+            Some(fields),
+        );
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], REQUEST_TARGET);
+        assert_eq!(line["request_id"], "1-a");
+        assert_eq!(line["operation_name"], "Heros");
+        assert_eq!(line["duration_ms"], serde_json::json!(1.5));
+        assert_eq!(line["errors"], 2);
+        assert!(line.get("message").is_none());
+    }
+
+    #[test]
+    fn message() {
+        let line = json_record(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("test_wundergraph::db")
+                .args(format_args!("{{\"not\": \"parsed\"}}"))
+                .build(),
+            None,
+        );
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "{\"not\": \"parsed\"}");
+        assert!(line.get("not").is_none());
+    }
+}
@@ -0,0 +1,69 @@
+/// Define a GraphQL enum stored in a `SMALLINT` column
+///
+/// Variants are written with their discriminant, which is the value stored
+/// in the database. Reading an unknown value returns a deserialization
+/// error, reported as a field error, instead of panicking.
+///
+/// `diesel::sql_types::SmallInt` has to be in scope where the macro is
+/// used, the `WundergraphValue` derive only accepts a type name.
+///
+/// ```ignore
+/// small_int_enum! {
+///     pub enum Episode {
+///         NEWHOPE = 1,
+///         EMPIRE = 2,
+///     }
+/// }
+/// ```
+macro_rules! small_int_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $value:expr,)+
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(
+            Debug, Copy, Clone, AsExpression, FromSqlRow, GraphQLEnum, WundergraphValue, Eq, PartialEq, Hash,
+        )]
+        #[sql_type = "SmallInt"]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant = $value,)+
+        }
+
+        impl<DB> diesel::serialize::ToSql<diesel::sql_types::SmallInt, DB> for $name
+        where
+            DB: diesel::backend::Backend,
+            i16: diesel::serialize::ToSql<diesel::sql_types::SmallInt, DB>,
+        {
+            fn to_sql<W: std::io::Write>(
+                &self,
+                out: &mut diesel::serialize::Output<'_, W, DB>,
+            ) -> diesel::serialize::Result {
+                diesel::serialize::ToSql::<diesel::sql_types::SmallInt, DB>::to_sql(
+                    &(*self as i16),
+                    out,
+                )
+            }
+        }
+
+        impl<DB> diesel::deserialize::FromSql<diesel::sql_types::SmallInt, DB> for $name
+        where
+            DB: diesel::backend::Backend,
+            i16: diesel::deserialize::FromSql<diesel::sql_types::SmallInt, DB>,
+        {
+            fn from_sql(bytes: Option<&DB::RawValue>) -> diesel::deserialize::Result<Self> {
+                let value = <i16 as diesel::deserialize::FromSql<
+                    diesel::sql_types::SmallInt,
+                    DB,
+                >>::from_sql(bytes)?;
+                $(
+                    if value == $name::$variant as i16 {
+                        return Ok($name::$variant);
+                    }
+                )+
+                Err(format!("Unknown {} value {}", stringify!($name), value).into())
+            }
+        }
+    };
+}
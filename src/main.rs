@@ -1,36 +1,65 @@
 use std::env;
 
+use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::{
     middleware, web,
     web::{Data, Json},
-    App, Error as ActixError, HttpResponse, HttpServer,
+    App, Error as ActixError, HttpRequest, HttpResponse, HttpServer, ResponseError,
 };
 use diesel::backend::Backend;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
-use diesel::{Connection, Identifiable};
-use env_logger;
+use diesel::Connection;
+use juniper::http::graphiql::graphiql_source;
+use juniper::http::playground::playground_source;
 use juniper::http::GraphQLRequest;
 use juniper::LookAheadSelection;
+use log::info;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Instant;
+use test_wundergraph::config::Config;
+use test_wundergraph::db::{self, MigrationMode};
 use test_wundergraph::generated::*;
-use test_wundergraph::*;
+use test_wundergraph::graphql::{GraphQLQueryParams, OperationKind, ServiceError};
+use test_wundergraph::health;
+use test_wundergraph::logging::{self, RequestLog};
+use test_wundergraph::media::{self, MediaConfig};
+use test_wundergraph::render::colorize::{self, ColorizeQuery};
+use test_wundergraph::render::crop;
+use test_wundergraph::render::file::{self, Conditions, FilePart, FileQuery};
+use test_wundergraph::render::legend::{self, LegendFormat, LegendQuery};
+use test_wundergraph::render::pool::RenderPool;
+use test_wundergraph::render::thumbnail::{self, ThumbnailQuery};
+use test_wundergraph::render::{self, RenderError};
+use test_wundergraph::timeline::{self, TimelineError, TimelineQuery, Track};
 use wundergraph::error::Result as WunderResult;
 use wundergraph::query_builder::selection::offset::ApplyOffset;
 use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler, QueryModifier};
 use wundergraph::scalar::WundergraphScalarValue;
 use wundergraph::WundergraphContext;
 
-#[macro_use]
-extern crate diesel;
-#[macro_use]
-extern crate juniper;
-#[macro_use]
-extern crate diesel_migrations;
-
 // actix integration stuff
 #[derive(Serialize, Deserialize, Debug)]
-pub struct GraphQLData(GraphQLRequest<WundergraphScalarValue>);
+#[serde(untagged)]
+pub enum GraphQLData {
+    Single(GraphQLRequest<WundergraphScalarValue>),
+    /// Operations executed in order, answered with an array of responses
+    Batch(Vec<GraphQLRequest<WundergraphScalarValue>>),
+}
+
+impl GraphQLData {
+    fn operation_names(&self) -> Vec<&str> {
+        let requests = match self {
+            GraphQLData::Single(req) => std::slice::from_ref(req),
+            GraphQLData::Batch(reqs) => reqs.as_slice(),
+        };
+        requests
+            .iter()
+            .filter_map(|req| req.operation_name())
+            .collect()
+    }
+}
 
 pub type DBConnection = diesel::PgConnection;
 
@@ -86,40 +115,349 @@ pub type Schema<Ctx> =
 struct AppState {
     schema: Arc<Schema<MyContext<DBConnection>>>,
     pool: Arc<Pool<ConnectionManager<DBConnection>>>,
+    migrations_current: Arc<AtomicBool>,
+    media: Arc<MediaConfig>,
+    render: RenderPool,
 }
 
 async fn graphql(
-    Json(GraphQLData(data)): Json<GraphQLData>,
+    req: HttpRequest,
+    Json(data): Json<GraphQLData>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    execute(data, st.get_ref().clone(), request_id(&req)).await
+}
+
+async fn graphql_get(
+    req: HttpRequest,
+    web::Query(params): web::Query<GraphQLQueryParams>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    if params.operation_kind() == Some(OperationKind::Mutation) {
+        return Ok(HttpResponse::MethodNotAllowed()
+            .header("Allow", "POST")
+            .json(serde_json::json!({
+                "errors": [{ "message": "Mutations are only allowed over POST" }]
+            })));
+    }
+    match params.into_request() {
+        Ok(data) => {
+            execute(
+                GraphQLData::Single(data),
+                st.get_ref().clone(),
+                request_id(&req),
+            )
+            .await
+        }
+        Err(message) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "errors": [{ "message": message }]
+        }))),
+    }
+}
+
+fn request_id(req: &HttpRequest) -> String {
+    req.headers()
+        .get("X-Request-Id")
+        .and_then(|id| id.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(logging::next_request_id)
+}
+
+/// Execution runs on the blocking thread pool as diesel connections are
+/// synchronous and would otherwise stall the actix worker
+async fn execute(
+    data: GraphQLData,
+    st: AppState,
+    request_id: String,
+) -> Result<HttpResponse, ActixError> {
+    let start = Instant::now();
+    let operation_name = data.operation_names().join(",");
+    let res = web::block(move || {
+        let conn = st.pool.get().map_err(ServiceError::DatabaseUnavailable)?;
+        let ctx = MyContext::new(conn);
+        let res = match data {
+            GraphQLData::Single(req) => serde_json::to_value(req.execute(&st.schema, &ctx)),
+            GraphQLData::Batch(reqs) => serde_json::to_value(
+                reqs.iter()
+                    .map(|req| req.execute(&st.schema, &ctx))
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        res.map_err(|e| ServiceError::Internal(e.to_string()))
+    })
+    .await
+    .map_err(ServiceError::from);
+
+    logging::log_request(&RequestLog {
+        request_id: &request_id,
+        operation_name: Some(operation_name.as_str()).filter(|name| !name.is_empty()),
+        duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        errors: res.as_ref().map_or(1, count_errors),
+    });
+
+    let mut response = match res {
+        Ok(body) => HttpResponse::Ok().json(body),
+        Err(e) => e.error_response(),
+    };
+    if let Ok(id) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-request-id"), id);
+    }
+    Ok(response)
+}
+
+fn count_errors(body: &serde_json::Value) -> usize {
+    match body {
+        serde_json::Value::Array(responses) => responses.iter().map(count_errors).sum(),
+        _ => body
+            .get("errors")
+            .and_then(|errors| errors.as_array())
+            .map_or(0, Vec::len),
+    }
+}
+
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(health::liveness())
+}
+
+async fn readyz(st: Data<AppState>) -> Result<HttpResponse, ActixError> {
+    let st = st.get_ref().clone();
+    let report = web::block(move || {
+        Ok::<_, ServiceError>(health::readiness(&st.pool, &st.migrations_current))
+    })
+    .await
+    .map_err(ServiceError::from)?;
+    if report.is_ok() {
+        Ok(HttpResponse::Ok().json(report))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(report))
+    }
+}
+
+async fn colormap_legend(
+    req: HttpRequest,
+    path: web::Path<(i32, String)>,
+    web::Query(query): web::Query<LegendQuery>,
     st: Data<AppState>,
 ) -> Result<HttpResponse, ActixError> {
-    let ctx = MyContext::new(st.get_ref().pool.get().expect("Fail to get pool"));
-    let res = data.execute(&st.get_ref().schema, &ctx);
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(&res)?))
+    let (id, extension) = path.into_inner();
+    let format = LegendFormat::from_extension(&extension).ok_or_else(|| {
+        RenderError::InvalidParameter(format!("Unsupported legend format: {}", extension))
+    })?;
+    let size = query.legend()?;
+    let pool = st.pool.clone();
+    let gradient = web::block(move || legend::load_gradient(&*pool.get()?, id))
+        .await
+        .map_err(RenderError::from)?;
+    let body = st
+        .render
+        .run(move || legend::render(&gradient, size, format))
+        .await
+        .map_err(RenderError::from)?;
+    Ok(render::image_response(&req, body, format.content_type()))
 }
 
-diesel_migrations::embed_migrations!("migrations");
+async fn image_render(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    web::Query(query): web::Query<ColorizeQuery>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    let id = path.into_inner();
+    let pool = st.pool.clone();
+    let (frame, gradient, bbox, query) = web::block(move || {
+        let conn = pool.get()?;
+        let frame = colorize::load_frame(&conn, id)?;
+        let gradient = colorize::load_colormap(&conn, &frame, query.colormap.as_deref())?;
+        let bbox = match query.crop() {
+            Some(c) => crop::load_box(&conn, id, c)?,
+            None => None,
+        };
+        Ok::<_, RenderError>((frame, gradient, bbox, query))
+    })
+    .await
+    .map_err(RenderError::from)?;
+    let media = st.media.clone();
+    let body = st
+        .render
+        .run(move || colorize::render(&media.dir, &frame, &gradient, &query, bbox.as_ref()))
+        .await
+        .map_err(RenderError::from)?;
+    Ok(render::image_response(&req, body, "image/png"))
+}
+
+enum ServedFile {
+    Stored(FilePart, &'static str),
+    /// Cropped frame, as PNG
+    Cropped(Vec<u8>),
+}
+
+async fn image_file(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    web::Query(query): web::Query<FileQuery>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    let id = path.into_inner();
+    let conditions = Conditions::from_request(&req);
+    let pool = st.pool.clone();
+    let (path, bbox) = web::block(move || {
+        let conn = pool.get()?;
+        let bbox = match query.crop() {
+            Some(c) => crop::load_box(&conn, id, c)?,
+            None => None,
+        };
+        Ok::<_, RenderError>((file::image_path(&conn, id)?, bbox))
+    })
+    .await
+    .map_err(RenderError::from)?;
+    let media = st.media.clone();
+    let served = st
+        .render
+        .run(move || {
+            let source = media::resolve(&media.dir, &path)?;
+            match bbox {
+                Some(bbox) => Ok(ServedFile::Cropped(crop::render(&source, &bbox)?)),
+                None => Ok::<_, RenderError>(ServedFile::Stored(
+                    file::read(&source, &path, &conditions)?,
+                    file::content_type(&path),
+                )),
+            }
+        })
+        .await
+        .map_err(RenderError::from)?;
+    match served {
+        ServedFile::Stored(part, content_type) => Ok(part.into_response(content_type, &st.render)),
+        ServedFile::Cropped(png) => Ok(render::image_response(&req, png, "image/png")),
+    }
+}
+
+async fn image_thumbnail(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    web::Query(query): web::Query<ThumbnailQuery>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    let id = path.into_inner();
+    let size = query.size()?;
+    let conditions = Conditions::from_request(&req);
+    let pool = st.pool.clone();
+    let path = web::block(move || file::image_path(&*pool.get()?, id))
+        .await
+        .map_err(RenderError::from)?;
+    let media = st.media.clone();
+    let part = st
+        .render
+        .run(move || {
+            let source = media::resolve(&media.dir, &path)?;
+            let thumbnail = thumbnail::cached(&media.thumbnail_dir, id, &source, &path, size)?;
+            file::read(&thumbnail, &path, &conditions)
+        })
+        .await
+        .map_err(RenderError::from)?;
+    Ok(part.into_response("image/png", &st.render))
+}
+
+async fn color_movie_timeline(
+    path: web::Path<i32>,
+    web::Query(query): web::Query<TimelineQuery>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    movie_timeline(Track::Images, path.into_inner(), query, st).await
+}
+
+async fn vector_movie_timeline(
+    path: web::Path<i32>,
+    web::Query(query): web::Query<TimelineQuery>,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    movie_timeline(Track::VectorData, path.into_inner(), query, st).await
+}
+
+async fn movie_timeline(
+    track: Track,
+    id: i32,
+    query: TimelineQuery,
+    st: Data<AppState>,
+) -> Result<HttpResponse, ActixError> {
+    let st = st.get_ref().clone();
+    let timeline = web::block(move || timeline::timeline(&*st.pool.get()?, track, id, &query))
+        .await
+        .map_err(TimelineError::from)?;
+    Ok(HttpResponse::Ok().json(timeline))
+}
+
+async fn graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(graphiql_source("/graphql"))
+}
+
+async fn playground() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(playground_source("/graphql"))
+}
+
+fn explorer_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/graphiql", web::get().to(graphiql))
+        .route("/playground", web::get().to(playground));
+}
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    println!("Hello, world!");
-
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    let db_url = format!(
-        "postgres://{}:{}@{}/{}",
-        env::var("POSTGRES_USER").unwrap_or_else(|_| "postgres".to_string()),
-        env::var("POSTGRES_PASSWORD").unwrap_or_else(|_| "postgres".to_string()),
-        env::var("POSTGRES_HOST").unwrap_or_else(|_| "localhost".to_string()),
-        env::var("POSTGRES_DB").unwrap_or_else(|_| "test_wunder".to_string())
-    );
-    env_logger::init();
-
-    let manager = ConnectionManager::<DBConnection>::new(db_url);
-    let pool = Pool::builder()
-        .max_size(1)
-        .build(manager)
-        .expect("Failed to init pool");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return Err(std::io::Error::other(e.to_string())),
+    };
+
+    logging::init(&config.logging);
+
+    let pool =
+        db::build_pool(&config.database.url(), &config.database.pool).expect("Failed to init pool");
+
+    let migrations_current = {
+        let conn = pool.get().expect("Fail to get pool");
+        match config.database.migrations {
+            MigrationMode::Run => {
+                let applied = db::run_migrations(&conn).expect("Failed to run migrations");
+                if applied.is_empty() {
+                    info!("Database schema is up to date");
+                }
+                for version in applied {
+                    info!("Applied migration {}", version);
+                }
+                true
+            }
+            MigrationMode::Check => {
+                let pending = db::pending_migrations(&conn).expect("Failed to check migrations");
+                if !pending.is_empty() {
+                    return Err(std::io::Error::other(format!(
+                        "Pending migrations: {}",
+                        pending.join(", ")
+                    )));
+                }
+                true
+            }
+            MigrationMode::Skip => false,
+        }
+    };
+
+    // Size the blocking pool used to execute queries after the connection
+    // pool, extra threads would only wait for a connection. Media files are
+    // read on the render pool so they don't take those threads.
+    let blocking_threads = config
+        .server
+        .blocking_threads
+        .unwrap_or(pool.max_size() as usize);
+    env::set_var("ACTIX_THREADPOOL", blocking_threads.to_string());
+    let render_threads = config
+        .server
+        .render_threads
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(4);
+    let render = RenderPool::new(render_threads);
 
     let query = Query::<MyContext<DBConnection>>::default();
     let mutation = Mutation::<MyContext<DBConnection>>::default();
@@ -127,20 +465,55 @@ async fn main() -> std::io::Result<()> {
 
     let schema = Arc::new(schema);
     let pool = Arc::new(pool);
-    let data = AppState { schema, pool };
+    let migrations_current = Arc::new(AtomicBool::new(migrations_current));
+    let media = Arc::new(config.media.clone());
+    let data = AppState {
+        schema,
+        pool,
+        migrations_current,
+        media,
+        render,
+    };
 
-    let my_url = env::var("MY_URL").unwrap_or_else(|_| String::from("127.0.0.1:8088"));
+    let my_url = config.server.bind.clone();
+    let with_explorer = config.features.graphiql;
 
-    println!("Started http server: {}", my_url);
+    info!("Started http server: {}", my_url);
+    if with_explorer {
+        info!("GraphiQL: http://{}/graphiql", my_url);
+    }
 
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .data(data.clone())
             .wrap(middleware::Logger::default())
-            .route("/graphql", web::get().to(graphql))
+            .route("/graphql", web::get().to(graphql_get))
             .route("/graphql", web::post().to(graphql))
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
+            .route(
+                "/colormaps/{id}/legend.{format}",
+                web::get().to(colormap_legend),
+            )
+            .route("/images/{id}/file", web::get().to(image_file))
+            .route("/images/{id}/thumbnail", web::get().to(image_thumbnail))
+            .route("/images/{id}/render", web::get().to(image_render))
+            .route(
+                "/color_movies/{id}/timeline",
+                web::get().to(color_movie_timeline),
+            )
+            .route(
+                "/vector_movies/{id}/timeline",
+                web::get().to(vector_movie_timeline),
+            );
+        if with_explorer {
+            app.configure(explorer_routes)
+        } else {
+            app
+        }
     })
     .bind(&my_url)
     .expect("Failed to start server")
-    .run().await
+    .run()
+    .await
 }
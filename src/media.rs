@@ -0,0 +1,85 @@
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Settings of the files referenced by `Image.path` and `Movie.path`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaConfig {
+    /// Directory the paths are relative to, no file outside of it is read
+    pub dir: PathBuf,
+    /// Where generated thumbnails are kept
+    pub thumbnail_dir: PathBuf,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("media"),
+            thumbnail_dir: PathBuf::from("cache/thumbnails"),
+        }
+    }
+}
+
+impl MediaConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dir.as_os_str().is_empty() {
+            return Err(String::from("media.dir must not be empty"));
+        }
+        if self.thumbnail_dir.as_os_str().is_empty() {
+            return Err(String::from("media.thumbnail_dir must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum MediaError {
+    /// No file at this path
+    NotFound(String),
+    /// The path leads out of the media directory
+    OutsideRoot(String),
+    Io(String, io::Error),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::NotFound(path) => write!(f, "File {} not found", path),
+            MediaError::OutsideRoot(path) => {
+                write!(f, "File {} is outside of the media directory", path)
+            }
+            MediaError::Io(path, e) => write!(f, "Cannot read {}: {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {}
+
+/// Location on disk of a stored media `path`
+///
+/// Relative paths are resolved from `root`, absolute ones are accepted when
+/// inside it. Paths going up with `..` are refused and symlinks are
+/// resolved before checking, so a path can't escape the media directory.
+/// Errors only mention `path`, never where the media directory is.
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf, MediaError> {
+    let not_found = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => MediaError::NotFound(path.to_owned()),
+        _ => MediaError::Io(path.to_owned(), e),
+    };
+    if Path::new(path).components().any(|c| c == Component::ParentDir) {
+        return Err(MediaError::OutsideRoot(path.to_owned()));
+    }
+    let root = root
+        .canonicalize()
+        .map_err(|e| MediaError::Io(String::from("media directory"), e))?;
+    let file = root.join(path).canonicalize().map_err(not_found)?;
+    if !file.starts_with(&root) {
+        return Err(MediaError::OutsideRoot(path.to_owned()));
+    }
+    if !file.is_file() {
+        return Err(MediaError::NotFound(path.to_owned()));
+    }
+    Ok(file)
+}
@@ -0,0 +1,435 @@
+use crate::generated::*;
+use diesel::associations::HasTable;
+use diesel::pg::Pg;
+use juniper::{meta, Executor, GraphQLType, Object, Registry};
+use juniper::{LookAheadArgument, LookAheadSelection, Selection, Value};
+use std::collections::HashMap;
+use wundergraph::error::Result;
+use wundergraph::graphql_type::{GraphqlWrapper, WundergraphGraphqlMapper};
+use wundergraph::query_builder::selection::fields::WundergraphBelongsTo;
+use wundergraph::query_builder::selection::LoadingHandler;
+use wundergraph::scalar::WundergraphScalarValue;
+use wundergraph::WundergraphContext;
+
+/// Content of a movie, the `ColorMovie` or `VectorMovie` linked to it
+///
+/// Target of the `Movie.content` `HasMany`: filters like the
+/// `movie_contents` view, resolves to `MovieContent` union values.
+#[derive(Debug, Clone)]
+pub struct MovieContents;
+
+impl HasTable for MovieContents {
+    type Table = movie_contents::table;
+
+    fn table() -> Self::Table {
+        movie_contents::table
+    }
+}
+
+/// Value of the `MovieContent` union, resolved beforehand like the member
+/// entity `type_name`
+#[derive(Debug)]
+pub struct MovieContent<Ctx> {
+    type_name: &'static str,
+    value: Object<WundergraphScalarValue>,
+    context: std::marker::PhantomData<Ctx>,
+}
+
+impl<Ctx> MovieContent<Ctx> {
+    fn new(type_name: &'static str, value: Value<WundergraphScalarValue>) -> Self {
+        MovieContent {
+            type_name,
+            value: match value {
+                Value::Object(value) => value,
+                _ => Object::with_capacity(0),
+            },
+            context: std::marker::PhantomData,
+        }
+    }
+
+    /// Fields of `selection` applying to this member, by response name
+    fn project<'a>(
+        &self,
+        selection: &'a [Selection<'a, WundergraphScalarValue>],
+        executor: &'a Executor<'a, Ctx, WundergraphScalarValue>,
+        object: &mut Object<WundergraphScalarValue>,
+    ) {
+        for s in selection {
+            match s {
+                Selection::Field(field) => {
+                    let field = &field.item;
+                    let key = field.alias.unwrap_or(field.name).item;
+                    if field.name.item == "__typename" {
+                        object.add_field(key, Value::scalar(self.type_name));
+                    } else if let Some(value) = self.value.get_field_value(key) {
+                        object.add_field(key, value.clone());
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let fragment = &fragment.item;
+                    if fragment
+                        .type_condition
+                        .map_or(true, |t| t.item == self.type_name)
+                    {
+                        self.project(&fragment.selection_set, executor, object);
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = executor.fragment_by_name(spread.item.name.item) {
+                        if fragment.type_condition.item == self.type_name {
+                            self.project(&fragment.selection_set, executor, object);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fields of `selection` applying to the union member `type_name`, out of
+/// the fragments on it
+fn member_fields<'a, Ctx>(
+    type_name: &str,
+    selection: &'a [Selection<'a, WundergraphScalarValue>],
+    executor: &'a Executor<'a, Ctx, WundergraphScalarValue>,
+    fields: &mut Vec<Selection<'a, WundergraphScalarValue>>,
+) {
+    for s in selection {
+        match s {
+            Selection::Field(_) => fields.push(s.clone()),
+            Selection::InlineFragment(fragment) => {
+                let fragment = &fragment.item;
+                if fragment
+                    .type_condition
+                    .map_or(true, |t| t.item == type_name)
+                {
+                    member_fields(type_name, &fragment.selection_set, executor, fields);
+                }
+            }
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = executor.fragment_by_name(spread.item.name.item) {
+                    if fragment.type_condition.item == type_name {
+                        member_fields(type_name, &fragment.selection_set, executor, fields);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<Ctx> GraphQLType<WundergraphScalarValue> for MovieContent<Ctx>
+where
+    Ctx: WundergraphContext + 'static,
+    <Ctx as WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+{
+    type Context = Ctx;
+    type TypeInfo = ();
+
+    fn name(_info: &()) -> Option<&str> {
+        Some("MovieContent")
+    }
+
+    fn meta<'r>(
+        info: &(),
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> meta::MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let types = [
+            registry.get_type::<GraphqlWrapper<ColorMovie, Pg, Ctx>>(&()),
+            registry.get_type::<GraphqlWrapper<VectorMovie, Pg, Ctx>>(&()),
+        ];
+        registry
+            .build_union_type::<Self>(info, &types)
+            .description("Frames or vector data of a movie")
+            .into_meta()
+    }
+
+    fn concrete_type_name(&self, _context: &Ctx, _info: &()) -> String {
+        self.type_name.to_owned()
+    }
+
+    /// Projects the resolved member, juniper would look up the fields of
+    /// fragment spreads on the union type itself
+    fn resolve(
+        &self,
+        _info: &(),
+        selection_set: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<Ctx, WundergraphScalarValue>,
+    ) -> Value<WundergraphScalarValue> {
+        let mut object = Object::with_capacity(self.value.field_count());
+        self.project(selection_set.unwrap_or_default(), executor, &mut object);
+        Value::Object(object)
+    }
+}
+
+impl<Ctx> WundergraphGraphqlMapper<Pg, Ctx> for MovieContents
+where
+    Ctx: WundergraphContext + 'static,
+    <Ctx as WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+{
+    type GraphQLType = MovieContent<Ctx>;
+}
+
+impl<Ctx> LoadingHandler<Pg, Ctx> for MovieContents
+where
+    Ctx: WundergraphContext + 'static,
+    <Ctx as WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+{
+    type Columns = <MovieContentIds as LoadingHandler<Pg, Ctx>>::Columns;
+    type FieldList = <MovieContentIds as LoadingHandler<Pg, Ctx>>::FieldList;
+    type PrimaryKeyIndex = <MovieContentIds as LoadingHandler<Pg, Ctx>>::PrimaryKeyIndex;
+    type Filter = <MovieContentIds as LoadingHandler<Pg, Ctx>>::Filter;
+
+    const FIELD_NAMES: &'static [&'static str] =
+        <MovieContentIds as LoadingHandler<Pg, Ctx>>::FIELD_NAMES;
+    const TYPE_NAME: &'static str = <MovieContentIds as LoadingHandler<Pg, Ctx>>::TYPE_NAME;
+    const TYPE_DESCRIPTION: Option<&'static str> =
+        <MovieContentIds as LoadingHandler<Pg, Ctx>>::TYPE_DESCRIPTION;
+
+    fn field_description(idx: usize) -> Option<&'static str> {
+        <MovieContentIds as LoadingHandler<Pg, Ctx>>::field_description(idx)
+    }
+
+    fn field_deprecation(idx: usize) -> Option<Option<&'static str>> {
+        <MovieContentIds as LoadingHandler<Pg, Ctx>>::field_deprecation(idx)
+    }
+}
+
+/// Load the `$entity`s linked to the movies `keys` by `$table.movie_id`
+/// into `contents`, resolved against the fragments on `$entity`
+///
+/// The `filter` argument of `Movie.content` applies to the `movie_contents`
+/// view, the entities are restricted to its `$content` ids.
+macro_rules! load_contents {
+    (
+        $entity:ident on $table:ident as $content:ident,
+        $contents:ident,
+        $global_args:ident,
+        $look_ahead:ident,
+        $selection:ident,
+        $keys:ident,
+        $executor:ident
+    ) => {{
+        use diesel::{ExpressionMethods, NullableExpressionMethods, QueryDsl, RunQueryDsl};
+        use juniper::LookAheadMethods;
+
+        let type_name = <$entity as LoadingHandler<Pg, Ctx>>::TYPE_NAME;
+        let mut fields = Vec::new();
+        member_fields(
+            type_name,
+            $selection.unwrap_or_default(),
+            $executor,
+            &mut fields,
+        );
+        let executor = $executor.type_sub_executor(Some(type_name), Some(&fields));
+        let mut query = $table::table
+            .into_boxed()
+            .select((
+                $table::movie_id,
+                <$entity as LoadingHandler<Pg, Ctx>>::get_select($look_ahead)?,
+            ))
+            .filter($table::movie_id.eq_any($keys));
+        if $look_ahead.argument("filter").is_some() {
+            let ids = <MovieContentIds as LoadingHandler<Pg, Ctx>>::build_query(
+                $global_args,
+                $look_ahead,
+            )?
+            .select(movie_contents::$content);
+            query = query.filter($table::id.nullable().eq_any(ids));
+        }
+        let values = <$entity as WundergraphBelongsTo<
+                    movies::table,
+                    Pg,
+                    Ctx,
+                    $table::movie_id,
+                >>::build_response(
+                    query.load($executor.context().get_connection())?,
+                    $global_args,
+                    $look_ahead,
+                    Some(&fields),
+                    &executor,
+                )?;
+        for (movie, values) in values {
+            $contents.entry(movie).or_insert_with(Vec::new).extend(
+                values
+                    .into_iter()
+                    .map(|value| MovieContent::<Ctx>::new(type_name, value)),
+            );
+        }
+    }};
+}
+
+impl<Ctx> WundergraphBelongsTo<movies::table, Pg, Ctx, movie_contents::id> for MovieContents
+where
+    Ctx: WundergraphContext + 'static,
+    <Ctx as WundergraphContext>::Connection: diesel::Connection<Backend = Pg>,
+{
+    type Key = i32;
+
+    fn resolve(
+        global_args: &[LookAheadArgument<WundergraphScalarValue>],
+        look_ahead: &LookAheadSelection<'_, WundergraphScalarValue>,
+        selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
+        keys: &[Option<i32>],
+        executor: &Executor<'_, Ctx, WundergraphScalarValue>,
+    ) -> Result<HashMap<Option<i32>, Vec<Value<WundergraphScalarValue>>>> {
+        let mut contents = HashMap::new();
+        load_contents!(ColorMovie on color_movies as color_movie_id, contents, global_args, look_ahead, selection, keys, executor);
+        load_contents!(VectorMovie on vector_movies as vector_movie_id, contents, global_args, look_ahead, selection, keys, executor);
+        Ok(contents
+            .into_iter()
+            .map(|(movie, contents)| {
+                let values = contents
+                    .iter()
+                    .map(|content| executor.resolve_into_value(&(), content))
+                    .collect();
+                (movie, values)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorFormat;
+    use diesel::{Connection, ExpressionMethods, PgConnection, RunQueryDsl};
+    use juniper::Variables;
+    use serde_json::json;
+    use wundergraph::query_builder::selection::{BoxedQuery, QueryModifier};
+
+    struct Context(PgConnection);
+
+    impl WundergraphContext for Context {
+        type Connection = PgConnection;
+
+        fn get_connection(&self) -> &PgConnection {
+            &self.0
+        }
+    }
+
+    impl<T> QueryModifier<T, Pg> for Context
+    where
+        T: LoadingHandler<Pg, Self>,
+    {
+        fn modify_query<'a>(
+            &self,
+            _select: &LookAheadSelection<'_, WundergraphScalarValue>,
+            query: BoxedQuery<'a, T, Pg, Self>,
+        ) -> Result<BoxedQuery<'a, T, Pg, Self>> {
+            Ok(query)
+        }
+    }
+
+    fn execute(ctx: &Context, query: &str) -> serde_json::Value {
+        let schema =
+            juniper::RootNode::new(Query::<Context>::default(), Mutation::<Context>::default());
+        let (result, errors) =
+            juniper::execute(query, None, &schema, &Variables::new(), ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        serde_json::to_value(&result).unwrap()
+    }
+
+    /// Run with `TEST_DATABASE_URL` pointing to a migrated database, changes
+    /// are rolled back
+    #[test]
+    #[ignore]
+    fn movie_content() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let ctx = Context(PgConnection::establish(&url).unwrap());
+        let conn = &ctx.0;
+        conn.begin_test_transaction().unwrap();
+
+        let movies: Vec<i32> = diesel::insert_into(movies::table)
+            .values(
+                ["content-both", "content-vector", "content-none"]
+                    .iter()
+                    .map(|name| (movies::identifier.eq(name), movies::name.eq(name)))
+                    .collect::<Vec<_>>(),
+            )
+            .returning(movies::id)
+            .get_results(conn)
+            .unwrap();
+        let (both, vector, none) = (movies[0], movies[1], movies[2]);
+        let colormap: i32 = diesel::insert_into(colormaps::table)
+            .values((
+                colormaps::name.eq("content-gray"),
+                colormaps::colors.eq(vec!["#000000", "#ffffff"]),
+                colormaps::positions.eq(vec![0.0, 1.0]),
+            ))
+            .returning(colormaps::id)
+            .get_result(conn)
+            .unwrap();
+        let color_movie: i32 = diesel::insert_into(color_movies::table)
+            .values((
+                color_movies::format.eq(ColorFormat::GRAY8),
+                color_movies::default_colormap.eq(colormap),
+                color_movies::movie_id.eq(both),
+            ))
+            .returning(color_movies::id)
+            .get_result(conn)
+            .unwrap();
+        let vector_movies: Vec<i32> = diesel::insert_into(vector_movies::table)
+            .values(vec![
+                vector_movies::movie_id.eq(both),
+                vector_movies::movie_id.eq(vector),
+            ])
+            .returning(vector_movies::id)
+            .get_results(conn)
+            .unwrap();
+
+        let result = execute(
+            &ctx,
+            &format!(
+                r#"{{
+                    Movies(filter: {{ id: {{ eq_any: [{}, {}, {}] }} }}, order: [{{ column: id }}]) {{
+                        id
+                        content {{
+                            __typename
+                            ... on ColorMovie {{ colorId: id format }}
+                            ...vector
+                        }}
+                    }}
+                }}
+                fragment vector on VectorMovie {{ vectorId: id }}"#,
+                both, vector, none
+            ),
+        );
+        assert_eq!(
+            result["Movies"],
+            json!([
+                { "id": both, "content": [
+                    { "__typename": "ColorMovie", "colorId": color_movie, "format": "GRAY8" },
+                    { "__typename": "VectorMovie", "vectorId": vector_movies[0] },
+                ] },
+                { "id": vector, "content": [
+                    { "__typename": "VectorMovie", "vectorId": vector_movies[1] },
+                ] },
+                { "id": none, "content": [] },
+            ])
+        );
+
+        // Filtering on the ids of the view
+        let result = execute(
+            &ctx,
+            &format!(
+                r#"{{
+                    Movies(filter: {{ id: {{ eq_any: [{}, {}, {}] }}, content: {{ color_movie_id: {{ is_null: false }} }} }}) {{
+                        id
+                        content(filter: {{ vector_movie_id: {{ is_null: false }} }}) {{ __typename }}
+                    }}
+                }}"#,
+                both, vector, none
+            ),
+        );
+        assert_eq!(
+            result["Movies"],
+            json!([{ "id": both, "content": [
+            { "__typename": "ColorMovie" },
+            { "__typename": "VectorMovie" },
+        ] }])
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use super::crop::{self, Crop};
+use super::{encode_png, open_image, RenderError};
+use crate::bounding_box::BoundingBox;
+use crate::colormap::{colormap_columns, Gradient, StoredGradient};
+use crate::generated::{color_movie_colormap, color_movies, colormaps, images};
+use crate::media;
+use crate::ColorFormat;
+use diesel::pg::PgConnection;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Query string of `GET /images/{id}/render`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorizeQuery {
+    /// Name of the colormap, the movie's `default_colormap` if not given
+    pub colormap: Option<String>,
+    /// Gray value mapped to the start of the colormap, 0 by default
+    pub min: Option<f64>,
+    /// Gray value mapped to the end of the colormap, the largest value of
+    /// the frame format by default
+    pub max: Option<f64>,
+    /// Crop to `Image.box`, or the movie's `pixels_box`
+    pub crop: bool,
+    /// Crop to the `pixels_box` of the movie in this cinema
+    pub cinema: Option<i32>,
+}
+
+impl ColorizeQuery {
+    pub fn crop(&self) -> Option<Crop> {
+        Crop::new(self.crop, self.cinema)
+    }
+
+    /// Validated `[min, max]` for frames in `format`
+    pub fn range(&self, format: ColorFormat) -> Result<(f64, f64), RenderError> {
+        let min = self.min.unwrap_or(0.0);
+        let max = match self.max {
+            Some(max) => max,
+            None => max_value(format)?,
+        };
+        if min.is_nan() || max.is_nan() || min >= max {
+            return Err(RenderError::InvalidParameter(format!(
+                "min must be lower than max, got [{}, {}]",
+                min, max
+            )));
+        }
+        Ok((min, max))
+    }
+}
+
+/// Frame of a `ColorMovie` and what's needed to colorize it
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub path: String,
+    pub format: ColorFormat,
+    pub color_movie_id: i32,
+    pub default_colormap: i32,
+}
+
+pub fn load_frame(conn: &PgConnection, id: i32) -> Result<Frame, RenderError> {
+    let (path, color_movie_id) = images::table
+        .find(id)
+        .select((images::path, images::color_movie_id))
+        .first::<(String, i32)>(conn)
+        .optional()?
+        .ok_or(RenderError::NotFound("Image", id))?;
+    let (format, default_colormap) = color_movies::table
+        .find(color_movie_id)
+        .select((color_movies::format, color_movies::default_colormap))
+        .first::<(ColorFormat, i32)>(conn)?;
+    Ok(Frame {
+        path,
+        format,
+        color_movie_id,
+        default_colormap,
+    })
+}
+
+/// Colormap named `name` among the ones of the movie of `frame` (its
+/// default one and the ones linked through `color_movie_colormap`), the
+/// default one if `name` is `None`
+pub fn load_colormap(
+    conn: &PgConnection,
+    frame: &Frame,
+    name: Option<&str>,
+) -> Result<Gradient, RenderError> {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return Ok(colormaps::table
+                .find(frame.default_colormap)
+                .select(colormap_columns::gradient)
+                .first::<StoredGradient>(conn)?
+                .gradient()?)
+        }
+    };
+    let linked = color_movie_colormap::table
+        .filter(color_movie_colormap::color_movie_id.eq(frame.color_movie_id))
+        .select(color_movie_colormap::colormap_id);
+    colormaps::table
+        .filter(colormaps::name.eq(name))
+        .filter(
+            colormaps::id
+                .eq(frame.default_colormap)
+                .or(colormaps::id.eq_any(linked)),
+        )
+        .select(colormap_columns::gradient)
+        .first::<StoredGradient>(conn)
+        .optional()?
+        .ok_or_else(|| {
+            RenderError::InvalidParameter(format!(
+                "ColorMovie {} has no colormap named {}",
+                frame.color_movie_id, name
+            ))
+        })?
+        .gradient()
+        .map_err(RenderError::from)
+}
+
+/// Largest gray value of a grayscale format
+fn max_value(format: ColorFormat) -> Result<f64, RenderError> {
+    match format {
+        ColorFormat::GRAY8 => Ok(f64::from(u8::MAX)),
+        ColorFormat::GRAY16 => Ok(f64::from(u16::MAX)),
+        _ => Err(RenderError::UnsupportedFrame(format!(
+            "{:?} frames can't be colorized, only GRAY8 and GRAY16",
+            format
+        ))),
+    }
+}
+
+/// Read the frame from the media directory, crop it to `bbox` if given and
+/// map its gray values onto `gradient`, encoded as PNG
+pub fn render(
+    media_dir: &Path,
+    frame: &Frame,
+    gradient: &Gradient,
+    query: &ColorizeQuery,
+    bbox: Option<&BoundingBox>,
+) -> Result<Vec<u8>, RenderError> {
+    let (min, max) = query.range(frame.format)?;
+    let file = media::resolve(media_dir, &frame.path)?;
+    let mut image = open_image(&file)?;
+    if let Some(bbox) = bbox {
+        image = crop::crop(&image, bbox)?;
+    }
+    encode_png(&colorize(&image, gradient, min, max)?)
+}
+
+/// Every gray value is looked up in a table computed once, 256 or 65536
+/// entries depending on the depth of the frame
+pub fn colorize(
+    image: &DynamicImage,
+    gradient: &Gradient,
+    min: f64,
+    max: f64,
+) -> Result<RgbaImage, RenderError> {
+    let lut = |depth: u32| -> Vec<[u8; 4]> {
+        (0..1u32 << depth)
+            .map(|v| gradient.color_at((f64::from(v) - min) / (max - min)))
+            .collect()
+    };
+    let (width, height) = image.dimensions();
+    match image {
+        DynamicImage::ImageLuma8(gray) => {
+            let lut = lut(8);
+            Ok(RgbaImage::from_fn(width, height, |x, y| {
+                Rgba(lut[usize::from(gray.get_pixel(x, y)[0])])
+            }))
+        }
+        DynamicImage::ImageLuma16(gray) => {
+            let lut = lut(16);
+            Ok(RgbaImage::from_fn(width, height, |x, y| {
+                Rgba(lut[usize::from(gray.get_pixel(x, y)[0])])
+            }))
+        }
+        _ => Err(RenderError::UnsupportedFrame(String::from(
+            "the file is not a grayscale image",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageBuffer, Luma};
+
+    fn gray() -> Gradient {
+        let colors = vec!["#000000".to_string(), "#ffffff".to_string()];
+        Gradient::new(&colors, &[0.0, 1.0]).unwrap()
+    }
+
+    fn range(
+        min: Option<f64>,
+        max: Option<f64>,
+        format: ColorFormat,
+    ) -> Result<(f64, f64), RenderError> {
+        ColorizeQuery {
+            min,
+            max,
+            ..ColorizeQuery::default()
+        }
+        .range(format)
+    }
+
+    fn pixels(image: &RgbaImage) -> Vec<u8> {
+        image.pixels().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn max_values() {
+        assert_eq!(max_value(ColorFormat::GRAY8).unwrap(), 255.0);
+        assert_eq!(max_value(ColorFormat::GRAY16).unwrap(), 65535.0);
+        for format in &[ColorFormat::RGB, ColorFormat::RGBA, ColorFormat::FLOAT32] {
+            assert_eq!(max_value(*format).unwrap_err().code(), "UNSUPPORTED_FRAME");
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range(None, None, ColorFormat::GRAY8).unwrap(), (0.0, 255.0));
+        assert_eq!(
+            range(None, None, ColorFormat::GRAY16).unwrap(),
+            (0.0, 65535.0)
+        );
+        assert_eq!(
+            range(Some(10.0), Some(20.0), ColorFormat::GRAY8).unwrap(),
+            (10.0, 20.0)
+        );
+        // An explicit max doesn't need the format
+        assert_eq!(
+            range(None, Some(1.0), ColorFormat::RGB).unwrap(),
+            (0.0, 1.0)
+        );
+        assert_eq!(
+            range(None, None, ColorFormat::RGB).unwrap_err().code(),
+            "UNSUPPORTED_FRAME"
+        );
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for (min, max) in &[
+            (Some(5.0), Some(5.0)),
+            (Some(300.0), None),
+            (Some(f64::NAN), None),
+            (None, Some(f64::NAN)),
+        ] {
+            let error = range(*min, *max, ColorFormat::GRAY8).unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER", "{:?} {:?}", min, max);
+        }
+    }
+
+    #[test]
+    fn colorize_gray8() {
+        let image =
+            DynamicImage::ImageLuma8(GrayImage::from_raw(4, 1, vec![0, 100, 150, 255]).unwrap());
+        let colored = colorize(&image, &gray(), 0.0, 255.0).unwrap();
+        assert_eq!(colored.dimensions(), (4, 1));
+        assert_eq!(pixels(&colored), vec![0, 100, 150, 255]);
+        assert!(colored.pixels().all(|p| p[3] == 255));
+
+        // Values out of [min, max] take the color of the closest end
+        let colored = colorize(&image, &gray(), 100.0, 150.0).unwrap();
+        assert_eq!(pixels(&colored), vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn colorize_gray16() {
+        let buffer: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_raw(3, 1, vec![0, 32768, 65535]).unwrap();
+        let image = DynamicImage::ImageLuma16(buffer);
+        let colored = colorize(&image, &gray(), 0.0, 65535.0).unwrap();
+        assert_eq!(pixels(&colored), vec![0, 128, 255]);
+    }
+
+    #[test]
+    fn colorize_color_frames() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+        let error = colorize(&image, &gray(), 0.0, 255.0).unwrap_err();
+        assert_eq!(error.code(), "UNSUPPORTED_FRAME");
+    }
+}
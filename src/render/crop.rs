@@ -0,0 +1,176 @@
+use super::{encode_frame_png, open_image, RenderError};
+use crate::bounding_box::BoundingBox;
+use crate::generated::{cinemas_movies, color_movies, images, movies};
+use diesel::pg::PgConnection;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use image::{DynamicImage, GenericImageView};
+use std::path::Path;
+
+/// Which stored box to crop a frame to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Crop {
+    /// `Image.box`, or the `pixels_box` of the movie when the image has none
+    Image,
+    /// `CinemasMovie.pixels_box` of this cinema, or the `pixels_box` of the
+    /// movie when the cinema has none
+    Cinema(i32),
+}
+
+impl Crop {
+    /// From the `crop` and `cinema` query parameters, giving a cinema
+    /// implies cropping
+    pub fn new(crop: bool, cinema: Option<i32>) -> Option<Self> {
+        match (crop, cinema) {
+            (_, Some(cinema)) => Some(Crop::Cinema(cinema)),
+            (true, None) => Some(Crop::Image),
+            (false, None) => None,
+        }
+    }
+}
+
+/// Box the image `id` is to be cropped to, `None` when nothing is stored
+pub fn load_box(
+    conn: &PgConnection,
+    id: i32,
+    crop: Crop,
+) -> Result<Option<BoundingBox>, RenderError> {
+    let (image_box, color_movie_id) = images::table
+        .find(id)
+        .select((images::box_, images::color_movie_id))
+        .first::<(Option<BoundingBox>, i32)>(conn)
+        .optional()?
+        .ok_or(RenderError::NotFound("Image", id))?;
+    if let (Crop::Image, Some(image_box)) = (crop, image_box) {
+        return Ok(Some(image_box));
+    }
+    let movie_id = color_movies::table
+        .find(color_movie_id)
+        .select(color_movies::movie_id)
+        .first::<Option<i32>>(conn)?;
+
+    let cinema_box = match crop {
+        Crop::Image => None,
+        Crop::Cinema(cinema) => {
+            let shown = match movie_id {
+                Some(movie_id) => cinemas_movies::table
+                    .filter(cinemas_movies::cinema_id.eq(cinema))
+                    .filter(cinemas_movies::movie_id.eq(movie_id))
+                    .select(cinemas_movies::pixels_box)
+                    .first::<Option<BoundingBox>>(conn)
+                    .optional()?,
+                None => None,
+            };
+            match shown {
+                Some(cinema_box) => cinema_box,
+                None => {
+                    return Err(RenderError::InvalidParameter(format!(
+                        "Cinema {} doesn't show the movie of Image {}",
+                        cinema, id
+                    )))
+                }
+            }
+        }
+    };
+    if cinema_box.is_some() {
+        return Ok(cinema_box);
+    }
+    match movie_id {
+        Some(movie_id) => Ok(movies::table
+            .find(movie_id)
+            .select(movies::pixels_box)
+            .first::<Option<BoundingBox>>(conn)?),
+        None => Ok(None),
+    }
+}
+
+/// Read the image file `source` and crop it to `bbox`, encoded as PNG
+pub fn render(source: &Path, bbox: &BoundingBox) -> Result<Vec<u8>, RenderError> {
+    let image = open_image(source)?;
+    encode_frame_png(&crop(&image, bbox)?)
+}
+
+/// Part of `image` inside `bbox`, in pixels from the top left corner
+///
+/// The box is widened to whole pixels and clipped to the image.
+pub fn crop(image: &DynamicImage, bbox: &BoundingBox) -> Result<DynamicImage, RenderError> {
+    let (width, height) = image.dimensions();
+    let clip = |v: f64, max: u32| v.max(0.0).min(f64::from(max)) as u32;
+    let (x0, y0) = (clip(bbox.xmin.floor(), width), clip(bbox.ymin.floor(), height));
+    let (x1, y1) = (clip(bbox.xmax.ceil(), width), clip(bbox.ymax.ceil(), height));
+    if x0 >= x1 || y0 >= y1 {
+        return Err(RenderError::UnsupportedFrame(format!(
+            "the box [{}, {}, {}, {}] is outside of the {}x{} frame",
+            bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax, width, height
+        )));
+    }
+    Ok(image.crop_imm(x0, y0, x1 - x0, y1 - y0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    /// 4x3 frame, each pixel is `x + 10 * y`
+    fn frame() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(4, 3, |x, y| {
+            image::Luma([(x + 10 * y) as u8])
+        }))
+    }
+
+    fn bbox(xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> BoundingBox {
+        BoundingBox::new(xmin, ymin, xmax, ymax).unwrap()
+    }
+
+    fn pixels(image: &DynamicImage) -> Vec<u8> {
+        image.to_luma8().into_raw()
+    }
+
+    #[test]
+    fn crop_parameters() {
+        assert_eq!(Crop::new(false, None), None);
+        assert_eq!(Crop::new(true, None), Some(Crop::Image));
+        assert_eq!(Crop::new(false, Some(3)), Some(Crop::Cinema(3)));
+        assert_eq!(Crop::new(true, Some(3)), Some(Crop::Cinema(3)));
+    }
+
+    #[test]
+    fn inside() {
+        let cropped = crop(&frame(), &bbox(1.0, 1.0, 3.0, 2.0)).unwrap();
+        assert_eq!(cropped.dimensions(), (2, 1));
+        assert_eq!(pixels(&cropped), vec![11, 12]);
+
+        // Widened to whole pixels
+        let cropped = crop(&frame(), &bbox(0.5, 1.5, 1.5, 1.7)).unwrap();
+        assert_eq!(cropped.dimensions(), (2, 1));
+        assert_eq!(pixels(&cropped), vec![10, 11]);
+    }
+
+    #[test]
+    fn clipped() {
+        let cropped = crop(&frame(), &bbox(-5.0, 1.0, 2.0, 10.0)).unwrap();
+        assert_eq!(cropped.dimensions(), (2, 2));
+        assert_eq!(pixels(&cropped), vec![10, 11, 20, 21]);
+
+        let cropped = crop(&frame(), &bbox(-1.0, -1.0, 100.0, 100.0)).unwrap();
+        assert_eq!(pixels(&cropped), pixels(&frame()));
+    }
+
+    #[test]
+    fn outside() {
+        let error = crop(&frame(), &bbox(10.0, 0.0, 20.0, 2.0)).unwrap_err();
+        assert_eq!(error.code(), "UNSUPPORTED_FRAME");
+        assert_eq!(
+            error.to_string(),
+            "Unsupported frame: the box [10, 0, 20, 2] is outside of the 4x3 frame"
+        );
+        // Touching the frame isn't enough, nor is an empty box
+        for outside in &[
+            bbox(4.0, 0.0, 6.0, 3.0),
+            bbox(1.0, -2.0, 3.0, 0.0),
+            bbox(1.0, 1.0, 1.0, 2.0),
+        ] {
+            assert!(crop(&frame(), outside).is_err(), "{:?}", outside);
+        }
+    }
+}
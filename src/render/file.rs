@@ -0,0 +1,427 @@
+use super::crop::Crop;
+use super::pool::RenderPool;
+use super::{RenderError, MAX_AGE};
+use crate::generated::images;
+use crate::media::MediaError;
+use actix_web::dev::SizedStream;
+use actix_web::error::BlockingError;
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{Error as ActixError, HttpRequest, HttpResponse};
+use diesel::pg::PgConnection;
+use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
+use futures::future::LocalBoxFuture;
+use futures::Stream;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::UNIX_EPOCH;
+
+/// Size of the chunks files are streamed in
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Conditional and range headers of a file request, copied out of the
+/// request so the file can be read on the render pool
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    range: Option<String>,
+    if_range: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Conditions {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let get = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        Self {
+            range: get(header::RANGE),
+            if_range: get(header::IF_RANGE),
+            if_none_match: get(header::IF_NONE_MATCH),
+        }
+    }
+}
+
+/// Query string of `GET /images/{id}/file`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileQuery {
+    /// Crop to `Image.box`, or the movie's `pixels_box`
+    pub crop: bool,
+    /// Crop to the `pixels_box` of the movie in this cinema
+    pub cinema: Option<i32>,
+}
+
+impl FileQuery {
+    pub fn crop(&self) -> Option<Crop> {
+        Crop::new(self.crop, self.cinema)
+    }
+}
+
+/// `path` of the image `id`, relative to the media directory
+pub fn image_path(conn: &PgConnection, id: i32) -> Result<String, RenderError> {
+    images::table
+        .find(id)
+        .select(images::path)
+        .first::<String>(conn)
+        .optional()?
+        .ok_or(RenderError::NotFound("Image", id))
+}
+
+/// What to answer to a file request, the open file is positioned at the
+/// first byte to send
+#[derive(Debug)]
+pub enum FilePart {
+    NotModified {
+        etag: String,
+    },
+    Full {
+        etag: String,
+        file: File,
+        len: u64,
+    },
+    /// Bytes `start..=end` of a file of `len` bytes
+    Partial {
+        etag: String,
+        file: File,
+        start: u64,
+        end: u64,
+        len: u64,
+    },
+    Unsatisfiable {
+        etag: String,
+        len: u64,
+    },
+}
+
+/// Open `file` for the part the request asks for, the bytes are streamed
+/// by the response
+///
+/// The `ETag` is made of the size and modification time of the file. Only
+/// single ranges are supported, a request for several ranges gets the whole
+/// file. `name` is the path reported in errors.
+pub fn read(file: &Path, name: &str, conditions: &Conditions) -> Result<FilePart, RenderError> {
+    let io_error = |e| RenderError::Media(MediaError::Io(name.to_owned(), e));
+    let mut f = File::open(file).map_err(io_error)?;
+    let metadata = f.metadata().map_err(io_error)?;
+    let len = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    let etag = format!("\"{:x}-{:x}\"", len, modified);
+
+    let matched = |tags: &str| {
+        tags.split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*")
+    };
+    if conditions.if_none_match.as_deref().is_some_and(matched) {
+        return Ok(FilePart::NotModified { etag });
+    }
+
+    // A range is only valid for the version of the file named by If-Range
+    let range = match conditions.if_range {
+        Some(ref tag) if tag.trim() != etag => None,
+        _ => conditions.range.as_deref(),
+    };
+    let (start, end) = match range.map(|r| parse_range(r, len)) {
+        None | Some(Range::Ignored) => return Ok(FilePart::Full { etag, file: f, len }),
+        Some(Range::Unsatisfiable) => return Ok(FilePart::Unsatisfiable { etag, len }),
+        Some(Range::Bytes(start, end)) => (start, end),
+    };
+    f.seek(SeekFrom::Start(start)).map_err(io_error)?;
+    Ok(FilePart::Partial {
+        etag,
+        file: f,
+        start,
+        end,
+        len,
+    })
+}
+
+/// Read of the next chunk, giving the file back with it
+type ChunkRead = LocalBoxFuture<'static, Result<(File, Bytes), BlockingError<io::Error>>>;
+
+/// The next `remaining` bytes of a file, read by chunks on the render pool
+pub struct ChunkedFile {
+    file: Option<File>,
+    remaining: u64,
+    reading: Option<ChunkRead>,
+    pool: RenderPool,
+}
+
+impl ChunkedFile {
+    pub fn new(file: File, remaining: u64, pool: RenderPool) -> Self {
+        ChunkedFile {
+            file: Some(file),
+            remaining,
+            reading: None,
+            pool,
+        }
+    }
+}
+
+impl Stream for ChunkedFile {
+    type Item = Result<Bytes, ActixError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(ref mut reading) = self.reading {
+            let read = match reading.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(read) => read,
+            };
+            self.reading = None;
+            return Poll::Ready(Some(match read {
+                Ok((file, chunk)) => {
+                    self.file = Some(file);
+                    self.remaining -= chunk.len() as u64;
+                    Ok(chunk)
+                }
+                Err(e) => Err(e.into()),
+            }));
+        }
+        let file = match self.file.take() {
+            Some(file) if self.remaining > 0 => file,
+            _ => return Poll::Ready(None),
+        };
+        let size = self.remaining.min(CHUNK_SIZE);
+        self.reading = Some(Box::pin(self.pool.run(move || {
+            let mut chunk = Vec::with_capacity(size as usize);
+            (&file).take(size).read_to_end(&mut chunk)?;
+            // The file shrank since its length was read
+            if chunk.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok((file, Bytes::from(chunk)))
+        })));
+        self.poll_next(cx)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Range {
+    /// `start..=end`, within the file
+    Bytes(u64, u64),
+    Unsatisfiable,
+    /// Malformed or multiple ranges, answered with the whole file
+    Ignored,
+}
+
+/// Parse `bytes=start-end`, `bytes=start-` or `bytes=-suffix_length`
+fn parse_range(header: &str, len: u64) -> Range {
+    let header = header.trim();
+    if !header.starts_with("bytes=") || header.contains(',') {
+        return Range::Ignored;
+    }
+    let spec = header["bytes=".len()..].trim();
+    let (first, last) = match spec.find('-') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => return Range::Ignored,
+    };
+    match (first.parse::<u64>().ok(), last.parse::<u64>().ok()) {
+        (None, None) => Range::Ignored,
+        _ if len == 0 => Range::Unsatisfiable,
+        (None, Some(0)) => Range::Unsatisfiable,
+        (None, Some(suffix)) => Range::Bytes(len.saturating_sub(suffix), len - 1),
+        (Some(start), _) if start >= len => Range::Unsatisfiable,
+        (Some(start), None) if last.is_empty() => Range::Bytes(start, len - 1),
+        (Some(start), Some(end)) if start <= end => Range::Bytes(start, end.min(len - 1)),
+        _ => Range::Ignored,
+    }
+}
+
+impl FilePart {
+    pub fn into_response(self, content_type: &str, pool: &RenderPool) -> HttpResponse {
+        let cache_control = format!("public, max-age={}", MAX_AGE);
+        match self {
+            FilePart::NotModified { etag } => HttpResponse::NotModified()
+                .header(header::ETAG, etag)
+                .header(header::CACHE_CONTROL, cache_control)
+                .finish(),
+            FilePart::Full { etag, file, len } => HttpResponse::Ok()
+                .content_type(content_type)
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::ETAG, etag)
+                .header(header::CACHE_CONTROL, cache_control)
+                .body(SizedStream::new(
+                    len,
+                    ChunkedFile::new(file, len, pool.clone()),
+                )),
+            FilePart::Partial {
+                etag,
+                file,
+                start,
+                end,
+                len,
+            } => {
+                let size = end - start + 1;
+                HttpResponse::build(StatusCode::PARTIAL_CONTENT)
+                    .content_type(content_type)
+                    .header(header::ACCEPT_RANGES, "bytes")
+                    .header(
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end, len),
+                    )
+                    .header(header::ETAG, etag)
+                    .header(header::CACHE_CONTROL, cache_control)
+                    .body(SizedStream::new(
+                        size,
+                        ChunkedFile::new(file, size, pool.clone()),
+                    ))
+            }
+            FilePart::Unsatisfiable { etag, len } => {
+                HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .header(header::ETAG, etag)
+                    .finish()
+            }
+        }
+    }
+}
+
+/// Content type of a media file, from its extension
+pub fn content_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("tif") | Some("tiff") => "image/tiff",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Range::Bytes(0, 9));
+        assert_eq!(parse_range("bytes=90-", 100), Range::Bytes(90, 99));
+        // The end is clamped to the file
+        assert_eq!(parse_range("bytes=90-200", 100), Range::Bytes(90, 99));
+        assert_eq!(parse_range("bytes=99-99", 100), Range::Bytes(99, 99));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_range("bytes=-10", 100), Range::Bytes(90, 99));
+        assert_eq!(parse_range("bytes=-200", 100), Range::Bytes(0, 99));
+        assert_eq!(parse_range("bytes=-0", 100), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=100-", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=150-200", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-5", 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignored_ranges() {
+        assert_eq!(parse_range("bytes=10-5", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=0-9,20-29", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=-", 100), Range::Ignored);
+        assert_eq!(parse_range("bytes=a-b", 100), Range::Ignored);
+        assert_eq!(parse_range("items=0-9", 100), Range::Ignored);
+    }
+
+    fn conditions(
+        range: Option<&str>,
+        if_range: Option<&str>,
+        if_none_match: Option<&str>,
+    ) -> Conditions {
+        Conditions {
+            range: range.map(String::from),
+            if_range: if_range.map(String::from),
+            if_none_match: if_none_match.map(String::from),
+        }
+    }
+
+    /// The bytes the response streams
+    fn collect(file: File, len: u64) -> Vec<u8> {
+        actix_rt::System::new("test").block_on(async move {
+            let mut stream = ChunkedFile::new(file, len, RenderPool::new(1));
+            let mut body = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                body.extend_from_slice(&chunk.unwrap());
+            }
+            body
+        })
+    }
+
+    #[test]
+    fn read_parts() {
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+        let mut source = tempfile::NamedTempFile::new().unwrap();
+        source.write_all(&content).unwrap();
+        let path = source.path();
+
+        let etag = match read(path, "file", &Conditions::default()).unwrap() {
+            FilePart::Full { etag, file, len } => {
+                assert_eq!(len, content.len() as u64);
+                assert_eq!(collect(file, len), content);
+                etag
+            }
+            part => panic!("unexpected {:?}", part),
+        };
+
+        let range = Some("bytes=65530-131080");
+        match read(path, "file", &conditions(range, None, None)).unwrap() {
+            FilePart::Partial {
+                file, start, end, ..
+            } => {
+                assert_eq!((start, end), (65530, 131080));
+                assert_eq!(collect(file, end - start + 1), &content[65530..=131080]);
+            }
+            part => panic!("unexpected {:?}", part),
+        }
+
+        // A stale If-Range gets the whole file
+        let part = read(path, "file", &conditions(range, Some("\"0-0\""), None)).unwrap();
+        assert!(matches!(part, FilePart::Full { .. }));
+        let part = read(path, "file", &conditions(range, Some(&etag), None)).unwrap();
+        assert!(matches!(part, FilePart::Partial { .. }));
+
+        let part = read(
+            path,
+            "file",
+            &conditions(Some("bytes=1000000-"), None, None),
+        )
+        .unwrap();
+        assert!(matches!(part, FilePart::Unsatisfiable { len, .. } if len == content.len() as u64));
+
+        let part = read(path, "file", &conditions(range, None, Some(&etag))).unwrap();
+        assert!(matches!(part, FilePart::NotModified { .. }));
+    }
+
+    #[test]
+    fn truncated_file() {
+        let mut source = tempfile::NamedTempFile::new().unwrap();
+        source.write_all(b"0123456789").unwrap();
+        let file = File::open(source.path()).unwrap();
+        source.as_file().set_len(4).unwrap();
+        let result = actix_rt::System::new("test").block_on(async move {
+            let mut stream = ChunkedFile::new(file, 10, RenderPool::new(1));
+            let first = stream.next().await.unwrap().unwrap();
+            (first, stream.next().await.unwrap().is_err())
+        });
+        assert_eq!(result, (Bytes::from_static(b"0123"), true));
+    }
+}
@@ -0,0 +1,263 @@
+use super::{encode_png, RenderError};
+use crate::colormap::{colormap_columns, to_hex, Gradient, StoredGradient};
+use crate::generated::colormaps;
+use diesel::pg::PgConnection;
+use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Largest width or height of a legend, in pixels
+const MAX_SIZE: u32 = 4096;
+
+/// Thickness and length of a legend when not given
+const DEFAULT_SIZE: (u32, u32) = (16, 256);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    /// 0 on the left, 1 on the right
+    #[default]
+    Horizontal,
+    /// 0 at the bottom, 1 at the top
+    Vertical,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LegendFormat {
+    Png,
+    Svg,
+}
+
+impl LegendFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "png" => Some(LegendFormat::Png),
+            "svg" => Some(LegendFormat::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            LegendFormat::Png => "image/png",
+            LegendFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// Query string of `GET /colormaps/{id}/legend.{png,svg}`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LegendQuery {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub orientation: Orientation,
+}
+
+/// Validated size and orientation of a legend
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Legend {
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+}
+
+impl LegendQuery {
+    pub fn legend(&self) -> Result<Legend, RenderError> {
+        let (thickness, length) = DEFAULT_SIZE;
+        let (width, height) = match self.orientation {
+            Orientation::Horizontal => (length, thickness),
+            Orientation::Vertical => (thickness, length),
+        };
+        let width = self.width.unwrap_or(width);
+        let height = self.height.unwrap_or(height);
+        for (name, size) in &[("width", width), ("height", height)] {
+            if *size == 0 || *size > MAX_SIZE {
+                return Err(RenderError::InvalidParameter(format!(
+                    "{} must be within [1, {}], got {}",
+                    name, MAX_SIZE, size
+                )));
+            }
+        }
+        Ok(Legend {
+            width,
+            height,
+            orientation: self.orientation,
+        })
+    }
+}
+
+pub fn load_gradient(conn: &PgConnection, id: i32) -> Result<Gradient, RenderError> {
+    colormaps::table
+        .find(id)
+        .select(colormap_columns::gradient)
+        .first::<StoredGradient>(conn)
+        .optional()?
+        .ok_or(RenderError::NotFound("Colormap", id))?
+        .gradient()
+        .map_err(RenderError::from)
+}
+
+pub fn render(
+    gradient: &Gradient,
+    legend: Legend,
+    format: LegendFormat,
+) -> Result<Vec<u8>, RenderError> {
+    match format {
+        LegendFormat::Png => encode_png(&png(gradient, legend)),
+        LegendFormat::Svg => Ok(svg(gradient, legend).into_bytes()),
+    }
+}
+
+pub fn png(gradient: &Gradient, legend: Legend) -> RgbaImage {
+    match legend.orientation {
+        Orientation::Horizontal => {
+            let colors = gradient.sample(legend.width as usize);
+            RgbaImage::from_fn(legend.width, legend.height, |x, _| {
+                Rgba(colors[x as usize])
+            })
+        }
+        Orientation::Vertical => {
+            let colors = gradient.sample(legend.height as usize);
+            RgbaImage::from_fn(legend.width, legend.height, |_, y| {
+                Rgba(colors[(legend.height - 1 - y) as usize])
+            })
+        }
+    }
+}
+
+/// Legend as a `linearGradient`, the browser interpolates between the stops
+pub fn svg(gradient: &Gradient, legend: Legend) -> String {
+    let (x2, y1) = match legend.orientation {
+        Orientation::Horizontal => (1, 0),
+        Orientation::Vertical => (0, 1),
+    };
+    let mut svg = format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            r#"<defs><linearGradient id="legend" x1="0" y1="{y1}" x2="{x2}" y2="0">"#
+        ),
+        w = legend.width,
+        h = legend.height,
+        x2 = x2,
+        y1 = y1,
+    );
+    for (position, color) in gradient.stops() {
+        let opaque = [color[0], color[1], color[2], 255];
+        // Writing to a String can't fail
+        let _ = write!(
+            svg,
+            r#"<stop offset="{}" stop-color="{}" stop-opacity="{}"/>"#,
+            position,
+            to_hex(opaque),
+            f64::from(color[3]) / 255.0
+        );
+    }
+    svg.push_str(r#"</linearGradient></defs><rect width="100%" height="100%" fill="url(#legend)"/></svg>"#);
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(width: Option<u32>, height: Option<u32>, orientation: Orientation) -> LegendQuery {
+        LegendQuery {
+            width,
+            height,
+            orientation,
+        }
+    }
+
+    fn gradient(colors: &[&str], positions: &[f64]) -> Gradient {
+        let colors = colors.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        Gradient::new(&colors, positions).unwrap()
+    }
+
+    #[test]
+    fn default_sizes() {
+        let legend = query(None, None, Orientation::default()).legend().unwrap();
+        assert_eq!(
+            (legend.width, legend.height, legend.orientation),
+            (256, 16, Orientation::Horizontal)
+        );
+        let legend = query(None, None, Orientation::Vertical).legend().unwrap();
+        assert_eq!((legend.width, legend.height), (16, 256));
+        let legend = query(Some(4096), None, Orientation::Vertical)
+            .legend()
+            .unwrap();
+        assert_eq!((legend.width, legend.height), (4096, 256));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        let error = |width, height| {
+            let error = query(width, height, Orientation::Horizontal)
+                .legend()
+                .unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER");
+            error.to_string()
+        };
+        assert_eq!(
+            error(Some(0), None),
+            "Invalid parameter: width must be within [1, 4096], got 0"
+        );
+        assert_eq!(
+            error(None, Some(4097)),
+            "Invalid parameter: height must be within [1, 4096], got 4097"
+        );
+    }
+
+    #[test]
+    fn png_orientation() {
+        let g = gradient(&["#000", "#fff"], &[0.0, 1.0]);
+        let (black, white) = (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+
+        let legend = query(Some(3), Some(2), Orientation::Horizontal)
+            .legend()
+            .unwrap();
+        let image = png(&g, legend);
+        assert_eq!(image.dimensions(), (3, 2));
+        for y in 0..2 {
+            assert_eq!(*image.get_pixel(0, y), black);
+            assert_eq!(*image.get_pixel(1, y), Rgba([128, 128, 128, 255]));
+            assert_eq!(*image.get_pixel(2, y), white);
+        }
+
+        // 0 at the bottom
+        let legend = query(Some(2), Some(3), Orientation::Vertical)
+            .legend()
+            .unwrap();
+        let image = png(&g, legend);
+        assert_eq!(image.dimensions(), (2, 3));
+        for x in 0..2 {
+            assert_eq!(*image.get_pixel(x, 2), black);
+            assert_eq!(*image.get_pixel(x, 0), white);
+        }
+    }
+
+    #[test]
+    fn svg_stops() {
+        let g = gradient(&["#000", "#ff000080"], &[0.0, 0.75]);
+        let legend = query(None, None, Orientation::Vertical).legend().unwrap();
+        assert_eq!(
+            svg(&g, legend),
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="256" viewBox="0 0 16 256">"#,
+                r#"<defs><linearGradient id="legend" x1="0" y1="1" x2="0" y2="0">"#,
+                r##"<stop offset="0" stop-color="#000000" stop-opacity="1"/>"##,
+                r##"<stop offset="0.75" stop-color="#ff0000" stop-opacity="0.5019607843137255"/>"##,
+                r#"</linearGradient></defs><rect width="100%" height="100%" fill="url(#legend)"/></svg>"#
+            )
+        );
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(LegendFormat::from_extension("png"), Some(LegendFormat::Png));
+        assert_eq!(LegendFormat::from_extension("svg"), Some(LegendFormat::Svg));
+        assert_eq!(LegendFormat::from_extension("jpg"), None);
+        assert_eq!(LegendFormat::Svg.content_type(), "image/svg+xml");
+    }
+}
@@ -0,0 +1,236 @@
+use actix_web::error::BlockingError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use crate::colormap::ColormapError;
+use crate::media::MediaError;
+use diesel::r2d2::PoolError;
+use diesel::result::Error as DieselError;
+use image::png::PngEncoder;
+use image::error::ImageError;
+use image::{ColorType, DynamicImage, GenericImageView, RgbaImage};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hasher;
+use std::path::Path;
+
+pub mod colorize;
+pub mod crop;
+pub mod file;
+pub mod legend;
+pub mod pool;
+pub mod thumbnail;
+
+/// How long clients may reuse a rendered image before revalidating it
+const MAX_AGE: u32 = 300;
+
+/// Failure of an image endpoint, answered with a JSON body
+/// `{ "error": { "code", "message" } }`
+#[derive(Debug)]
+pub enum RenderError {
+    /// The entity the image is made from doesn't exist, `(entity, id)`
+    NotFound(&'static str, i32),
+    InvalidParameter(String),
+    Media(MediaError),
+    /// The file can't be read as an image
+    Decoding(String),
+    /// The file is in an image format that isn't decoded
+    UnsupportedFormat(String),
+    /// The image can't be rendered this way, e.g. colorizing an RGB frame
+    UnsupportedFrame(String),
+    /// The stored colormap can't be rendered, it predates validation
+    InvalidColormap(ColormapError),
+    DatabaseUnavailable(PoolError),
+    Database(DieselError),
+    ExecutorUnavailable,
+    Encoding(String),
+}
+
+impl RenderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RenderError::NotFound(..) => "NOT_FOUND",
+            RenderError::InvalidParameter(_) => "INVALID_PARAMETER",
+            RenderError::Media(MediaError::NotFound(_)) => "FILE_NOT_FOUND",
+            RenderError::Media(MediaError::OutsideRoot(_)) => "FORBIDDEN_PATH",
+            RenderError::Media(MediaError::Io(..)) => "IO_ERROR",
+            RenderError::Decoding(_) => "DECODING_ERROR",
+            RenderError::UnsupportedFormat(_) => "UNSUPPORTED_MEDIA_TYPE",
+            RenderError::UnsupportedFrame(_) => "UNSUPPORTED_FRAME",
+            RenderError::InvalidColormap(_) => "INVALID_COLORMAP",
+            RenderError::DatabaseUnavailable(_) => "DATABASE_UNAVAILABLE",
+            RenderError::Database(_) => "DATABASE_ERROR",
+            RenderError::ExecutorUnavailable => "EXECUTOR_UNAVAILABLE",
+            RenderError::Encoding(_) => "ENCODING_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::NotFound(entity, id) => write!(f, "{} {} not found", entity, id),
+            RenderError::InvalidParameter(e) => write!(f, "Invalid parameter: {}", e),
+            RenderError::Media(e) => e.fmt(f),
+            RenderError::Decoding(e) => write!(f, "Failed to decode image: {}", e),
+            RenderError::UnsupportedFormat(e) => write!(f, "Unsupported image format: {}", e),
+            RenderError::UnsupportedFrame(e) => write!(f, "Unsupported frame: {}", e),
+            RenderError::InvalidColormap(e) => e.fmt(f),
+            RenderError::DatabaseUnavailable(e) => write!(f, "Database unavailable: {}", e),
+            RenderError::Database(e) => write!(f, "Database error: {}", e),
+            RenderError::ExecutorUnavailable => write!(f, "Request executor unavailable"),
+            RenderError::Encoding(e) => write!(f, "Failed to encode image: {}", e),
+        }
+    }
+}
+
+impl From<MediaError> for RenderError {
+    fn from(e: MediaError) -> Self {
+        RenderError::Media(e)
+    }
+}
+
+impl From<ColormapError> for RenderError {
+    fn from(e: ColormapError) -> Self {
+        RenderError::InvalidColormap(e)
+    }
+}
+
+impl From<PoolError> for RenderError {
+    fn from(e: PoolError) -> Self {
+        RenderError::DatabaseUnavailable(e)
+    }
+}
+
+impl From<DieselError> for RenderError {
+    fn from(e: DieselError) -> Self {
+        RenderError::Database(e)
+    }
+}
+
+impl From<BlockingError<RenderError>> for RenderError {
+    fn from(e: BlockingError<RenderError>) -> Self {
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => RenderError::ExecutorUnavailable,
+        }
+    }
+}
+
+impl ResponseError for RenderError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RenderError::NotFound(..) => StatusCode::NOT_FOUND,
+            RenderError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            RenderError::Media(MediaError::NotFound(_)) => StatusCode::NOT_FOUND,
+            RenderError::Media(MediaError::OutsideRoot(_)) => StatusCode::FORBIDDEN,
+            RenderError::UnsupportedFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RenderError::UnsupportedFrame(_) | RenderError::InvalidColormap(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            RenderError::DatabaseUnavailable(_) | RenderError::ExecutorUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            RenderError::Media(MediaError::Io(..))
+            | RenderError::Decoding(_)
+            | RenderError::Database(_)
+            | RenderError::Encoding(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": { "code": self.code(), "message": self.to_string() }
+        }))
+    }
+}
+
+/// Decode the image file `path`, PNG, JPEG and TIFF files are supported
+pub fn open_image(path: &Path) -> Result<DynamicImage, RenderError> {
+    image::open(path).map_err(|e| match e {
+        ImageError::Unsupported(e) => RenderError::UnsupportedFormat(e.to_string()),
+        e => RenderError::Decoding(e.to_string()),
+    })
+}
+
+pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, RenderError> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(image, image.width(), image.height(), ColorType::Rgba8)
+        .map_err(|e| RenderError::Encoding(e.to_string()))?;
+    Ok(png)
+}
+
+/// Frame as PNG, grayscale frames keep their depth, others are converted
+/// to 8 bit RGBA
+pub fn encode_frame_png(image: &DynamicImage) -> Result<Vec<u8>, RenderError> {
+    let (width, height) = image.dimensions();
+    let (data, color) = match image {
+        DynamicImage::ImageLuma8(gray) => (gray.as_raw().clone(), ColorType::L8),
+        // PNG samples are big endian, image buffers are in native order
+        DynamicImage::ImageLuma16(gray) => (
+            gray.as_raw().iter().flat_map(|v| v.to_be_bytes().to_vec()).collect(),
+            ColorType::L16,
+        ),
+        _ => return encode_png(&image.to_rgba8()),
+    };
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(&data, width, height, color)
+        .map_err(|e| RenderError::Encoding(e.to_string()))?;
+    Ok(png)
+}
+
+/// Answer with a rendered image, or `304 Not Modified` when the client
+/// already has it
+///
+/// Images are rendered from database rows that may change, the `ETag` is a
+/// hash of the bytes so revalidation works whatever changed.
+pub fn image_response(req: &HttpRequest, body: Vec<u8>, content_type: &str) -> HttpResponse {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&body);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    let cache_control = format!("public, max-age={}", MAX_AGE);
+
+    let fresh = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if fresh {
+        return HttpResponse::NotModified()
+            .header(header::ETAG, etag)
+            .header(header::CACHE_CONTROL, cache_control)
+            .finish();
+    }
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    #[test]
+    fn open_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = dir.path().join("frame.jpg");
+        GrayImage::from_pixel(8, 4, image::Luma([128])).save(&jpeg).unwrap();
+        assert_eq!(open_image(&jpeg).unwrap().dimensions(), (8, 4));
+
+        let gif = dir.path().join("frame.gif");
+        std::fs::write(&gif, b"GIF89a").unwrap();
+        let error = open_image(&gif).unwrap_err();
+        assert_eq!(error.code(), "UNSUPPORTED_MEDIA_TYPE");
+        assert_eq!(error.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let broken = dir.path().join("frame.png");
+        std::fs::write(&broken, b"not a png").unwrap();
+        assert_eq!(open_image(&broken).unwrap_err().code(), "DECODING_ERROR");
+    }
+}
@@ -0,0 +1,70 @@
+use actix_web::error::BlockingError;
+use futures::channel::oneshot;
+use std::fmt;
+use std::future::Future;
+use threadpool::ThreadPool;
+
+/// Threads reading media files and rendering images
+///
+/// Kept apart from the blocking pool executing database queries, so slow
+/// downloads and large renders don't hold back GraphQL requests.
+#[derive(Debug, Clone)]
+pub struct RenderPool {
+    pool: ThreadPool,
+}
+
+impl RenderPool {
+    pub fn new(threads: usize) -> Self {
+        RenderPool {
+            pool: ThreadPool::with_name(String::from("render"), threads),
+        }
+    }
+
+    /// Execute `f` on the pool, the counterpart of `web::block`
+    ///
+    /// A job whose future was dropped before its turn isn't run. A panic is
+    /// reported as `BlockingError::Canceled`.
+    pub fn run<F, T, E>(&self, f: F) -> impl Future<Output = Result<T, BlockingError<E>>>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + fmt::Debug + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.pool.execute(move || {
+            if !tx.is_canceled() {
+                let _ = tx.send(f());
+            }
+        });
+        async move {
+            match rx.await {
+                Ok(res) => res.map_err(BlockingError::Error),
+                Err(_) => Err(BlockingError::Canceled),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let pool = RenderPool::new(2);
+        actix_rt::System::new("test").block_on(async move {
+            assert_eq!(pool.run(|| Ok::<_, ()>(42)).await.unwrap(), 42);
+            match pool.run(|| Err::<(), _>("failed")).await {
+                Err(BlockingError::Error(e)) => assert_eq!(e, "failed"),
+                res => panic!("unexpected {:?}", res),
+            }
+            match pool
+                .run(|| -> Result<(), ()> { panic!("render failed") })
+                .await
+            {
+                Err(BlockingError::Canceled) => {}
+                res => panic!("unexpected {:?}", res),
+            }
+        });
+    }
+}
@@ -0,0 +1,84 @@
+use super::{encode_png, open_image, RenderError};
+use crate::media::MediaError;
+use image::GenericImageView;
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Largest side of a thumbnail, in pixels
+const MAX_SIZE: u32 = 1024;
+
+const DEFAULT_SIZE: u32 = 256;
+
+/// Query string of `GET /images/{id}/thumbnail`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailQuery {
+    /// Longest side of the thumbnail, 256 by default
+    pub size: Option<u32>,
+}
+
+impl ThumbnailQuery {
+    pub fn size(&self) -> Result<u32, RenderError> {
+        match self.size.unwrap_or(DEFAULT_SIZE) {
+            size @ 1..=MAX_SIZE => Ok(size),
+            size => Err(RenderError::InvalidParameter(format!(
+                "size must be within [1, {}], got {}",
+                MAX_SIZE, size
+            ))),
+        }
+    }
+}
+
+/// Thumbnail of the image file `source` fitting in `size` x `size`, as a PNG
+/// file of `cache_dir`, created if missing
+///
+/// Cached files are named after the image id, the size and the modification
+/// time of the source, a modified source gets a new thumbnail. Images
+/// smaller than `size` aren't enlarged. `name` is the path reported in
+/// errors.
+pub fn cached(
+    cache_dir: &Path,
+    id: i32,
+    source: &Path,
+    name: &str,
+    size: u32,
+) -> Result<PathBuf, RenderError> {
+    let io_error = |e| RenderError::Media(MediaError::Io(name.to_owned(), e));
+    let modified = fs::metadata(source)
+        .and_then(|m| m.modified())
+        .map_err(io_error)?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let dir = cache_dir.join(id.to_string());
+    let thumbnail = dir.join(format!("{}-{:x}.png", size, modified));
+    if thumbnail.is_file() {
+        return Ok(thumbnail);
+    }
+
+    let image = open_image(source)?;
+    let (width, height) = image.dimensions();
+    let image = if width > size || height > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+    let png = encode_png(&image.to_rgba8())?;
+
+    // Written to a uniquely named file of the same directory then renamed,
+    // concurrent requests never read a partial file
+    let cache_error = |e| RenderError::Media(MediaError::Io(String::from("thumbnail cache"), e));
+    fs::create_dir_all(&dir).map_err(cache_error)?;
+    let mut partial = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".tmp")
+        .tempfile_in(&dir)
+        .map_err(cache_error)?;
+    partial.write_all(&png).map_err(cache_error)?;
+    partial
+        .persist(&thumbnail)
+        .map_err(|e| cache_error(e.error))?;
+    Ok(thumbnail)
+}
@@ -0,0 +1,459 @@
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use crate::generated::{color_movies, vector_movies};
+use diesel::pg::Pg;
+use diesel::r2d2::PoolError;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{Array, Double, Int4, Int8, Nullable};
+use diesel::{sql_query, Connection, OptionalExtension, QueryDsl, RunQueryDsl};
+use juniper::FieldError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Most buckets of a timeline
+const MAX_BUCKETS: i32 = 10_000;
+
+pub(crate) const DEFAULT_BUCKETS: i32 = 100;
+
+/// Failure of a timeline, answered with a JSON body
+/// `{ "error": { "code", "message" } }` by the REST endpoints and as a field
+/// error with a `code` extension by GraphQL
+#[derive(Debug)]
+pub enum TimelineError {
+    /// The movie doesn't exist, `(entity, id)`
+    NotFound(&'static str, i32),
+    InvalidParameter(String),
+    DatabaseUnavailable(PoolError),
+    Database(DieselError),
+    ExecutorUnavailable,
+}
+
+impl TimelineError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TimelineError::NotFound(..) => "NOT_FOUND",
+            TimelineError::InvalidParameter(_) => "INVALID_PARAMETER",
+            TimelineError::DatabaseUnavailable(_) => "DATABASE_UNAVAILABLE",
+            TimelineError::Database(_) => "DATABASE_ERROR",
+            TimelineError::ExecutorUnavailable => "EXECUTOR_UNAVAILABLE",
+        }
+    }
+
+    pub(crate) fn into_field_error(self) -> FieldError<WundergraphScalarValue> {
+        FieldError::new(&self, graphql_value!({ "code": (self.code()) }))
+    }
+
+    /// Field error of the timeline of the movie `id` in a list of movies
+    pub(crate) fn into_movie_field_error(self, id: i32) -> FieldError<WundergraphScalarValue> {
+        FieldError::new(&self, graphql_value!({ "code": (self.code()), "id": (id) }))
+    }
+}
+
+impl fmt::Display for TimelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimelineError::NotFound(entity, id) => write!(f, "{} {} not found", entity, id),
+            TimelineError::InvalidParameter(e) => write!(f, "Invalid parameter: {}", e),
+            TimelineError::DatabaseUnavailable(e) => write!(f, "Database unavailable: {}", e),
+            TimelineError::Database(e) => write!(f, "Database error: {}", e),
+            TimelineError::ExecutorUnavailable => write!(f, "Request executor unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for TimelineError {}
+
+impl From<PoolError> for TimelineError {
+    fn from(e: PoolError) -> Self {
+        TimelineError::DatabaseUnavailable(e)
+    }
+}
+
+impl From<DieselError> for TimelineError {
+    fn from(e: DieselError) -> Self {
+        TimelineError::Database(e)
+    }
+}
+
+impl From<BlockingError<TimelineError>> for TimelineError {
+    fn from(e: BlockingError<TimelineError>) -> Self {
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => TimelineError::ExecutorUnavailable,
+        }
+    }
+}
+
+impl ResponseError for TimelineError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TimelineError::NotFound(..) => StatusCode::NOT_FOUND,
+            TimelineError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            TimelineError::DatabaseUnavailable(_) | TimelineError::ExecutorUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            TimelineError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": { "code": self.code(), "message": self.to_string() }
+        }))
+    }
+}
+
+/// Frames of a movie, `Image`s of a `ColorMovie` or `VectorData` of a
+/// `VectorMovie`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Track {
+    Images,
+    VectorData,
+}
+
+impl Track {
+    /// `(entity, table, column referencing the movie)`
+    fn names(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Track::Images => ("ColorMovie", "images", "color_movie_id"),
+            Track::VectorData => ("VectorMovie", "vector_data", "vector_movie_id"),
+        }
+    }
+
+    fn movie_exists<C>(self, conn: &C, id: i32) -> Result<bool, TimelineError>
+    where
+        C: Connection<Backend = Pg>,
+    {
+        let found = match self {
+            Track::Images => color_movies::table
+                .find(id)
+                .select(color_movies::id)
+                .first::<i32>(conn)
+                .optional()?,
+            Track::VectorData => vector_movies::table
+                .find(id)
+                .select(vector_movies::id)
+                .first::<i32>(conn)
+                .optional()?,
+        };
+        Ok(found.is_some())
+    }
+}
+
+/// Query string of `GET /color_movies/{id}/timeline` and
+/// `GET /vector_movies/{id}/timeline`, times in seconds since the epoch
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimelineQuery {
+    /// Start of the timeline, the first frame by default
+    pub from: Option<f64>,
+    /// End of the timeline, included, the last frame by default
+    pub to: Option<f64>,
+    pub buckets: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timeline {
+    pub from: f64,
+    pub to: f64,
+    /// Every bucket of the range, empty ones included
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub start: f64,
+    pub end: f64,
+    pub count: i64,
+    /// First frame of the bucket
+    pub frame: Option<Frame>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Frame {
+    pub id: i32,
+    pub time: f64,
+}
+
+#[derive(QueryableByName)]
+struct BucketRow {
+    #[sql_type = "Int4"]
+    movie: i32,
+    #[sql_type = "Int4"]
+    bucket: i32,
+    #[sql_type = "Int8"]
+    count: i64,
+    #[sql_type = "Int4"]
+    id: i32,
+    #[sql_type = "Double"]
+    time: f64,
+}
+
+#[derive(QueryableByName)]
+struct Bounds {
+    #[sql_type = "Int4"]
+    movie: i32,
+    #[sql_type = "Nullable<Double>"]
+    first: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    last: Option<f64>,
+}
+
+impl TimelineQuery {
+    /// Number of buckets, once the query is checked
+    fn buckets(&self) -> Result<i32, TimelineError> {
+        let n = self.buckets.unwrap_or(DEFAULT_BUCKETS);
+        if !(1..=MAX_BUCKETS).contains(&n) {
+            return Err(TimelineError::InvalidParameter(format!(
+                "buckets must be within [1, {}], got {}",
+                MAX_BUCKETS, n
+            )));
+        }
+        for (name, time) in &[("from", self.from), ("to", self.to)] {
+            match time {
+                Some(t) if !t.is_finite() => {
+                    return Err(TimelineError::InvalidParameter(format!(
+                        "{} must be a finite number, got {}",
+                        name, t
+                    )))
+                }
+                _ => {}
+            }
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                return Err(TimelineError::InvalidParameter(format!(
+                    "from must be lower than to, got [{}, {}]",
+                    from, to
+                )));
+            }
+        }
+        Ok(n)
+    }
+
+    /// Range of the timeline of a movie whose frames span `[first, last]`,
+    /// `None` when it has no frames
+    fn range(
+        &self,
+        first: Option<f64>,
+        last: Option<f64>,
+    ) -> Result<Option<(f64, f64)>, TimelineError> {
+        match (self.from.or(first), self.to.or(last)) {
+            (Some(from), Some(to)) if from < to => Ok(Some((from, to))),
+            // Every frame at the same time
+            (Some(from), Some(_)) if self.from.is_none() && self.to.is_none() => {
+                Ok(Some((from, from + 1.0)))
+            }
+            (Some(from), Some(to)) => Err(TimelineError::InvalidParameter(format!(
+                "from must be lower than to, got [{}, {}]",
+                from, to
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Frames of the movie `id` counted in `buckets` slices of equal duration
+/// between `from` and `to`, with the first frame of each
+///
+/// Bucketing is done by Postgres in one pass over the frames of the range,
+/// using the `(movie, time)` index of the track.
+pub fn timeline<C>(
+    conn: &C,
+    track: Track,
+    id: i32,
+    query: &TimelineQuery,
+) -> Result<Timeline, TimelineError>
+where
+    C: Connection<Backend = Pg>,
+{
+    query.buckets()?;
+    if !track.movie_exists(conn, id)? {
+        return Err(TimelineError::NotFound(track.names().0, id));
+    }
+    let (_, timeline) = movie_timelines(conn, track, &[id], query)?.remove(0);
+    timeline
+}
+
+/// Timeline of the movie `id`, or why it can't be computed
+pub(crate) type MovieTimeline = (i32, Result<Timeline, TimelineError>);
+
+/// [`timeline`](fn.timeline.html) of each of the movies `ids`, known to exist
+///
+/// Takes two queries whatever the number of movies, three when `from` or
+/// `to` defaults to the first or last frame. An invalid query fails as a
+/// whole, a range left invalid by the frames of a movie only fails its
+/// timeline.
+pub(crate) fn movie_timelines<C>(
+    conn: &C,
+    track: Track,
+    ids: &[i32],
+    query: &TimelineQuery,
+) -> Result<Vec<MovieTimeline>, TimelineError>
+where
+    C: Connection<Backend = Pg>,
+{
+    let (_, table, series) = track.names();
+    let n = query.buckets()?;
+    let bounds = match (query.from, query.to) {
+        (Some(_), Some(_)) => HashMap::new(),
+        _ => sql_query(format!(
+            "SELECT {series} AS movie, extract(epoch FROM min(time))::float8 AS first, \
+             extract(epoch FROM max(time))::float8 AS last \
+             FROM {table} WHERE {series} = ANY($1) GROUP BY {series}",
+            table = table,
+            series = series
+        ))
+        .bind::<Array<Int4>, _>(ids)
+        .load::<Bounds>(conn)?
+        .into_iter()
+        .map(|b| (b.movie, (b.first, b.last)))
+        .collect(),
+    };
+
+    let mut timelines = HashMap::new();
+    let (mut movies, mut starts, mut ends) = (Vec::new(), Vec::new(), Vec::new());
+    for &id in ids {
+        let (first, last) = bounds.get(&id).copied().unwrap_or((None, None));
+        let timeline = query.range(first, last).map(|range| match range {
+            Some((from, to)) => {
+                movies.push(id);
+                starts.push(from);
+                ends.push(to);
+                let width = (to - from) / f64::from(n);
+                let buckets = (0..n)
+                    .map(|i| Bucket {
+                        start: from + width * f64::from(i),
+                        end: from + width * f64::from(i + 1),
+                        count: 0,
+                        frame: None,
+                    })
+                    .collect();
+                Timeline { from, to, buckets }
+            }
+            None => Timeline {
+                from: query.from.unwrap_or(0.0),
+                to: query.to.unwrap_or(0.0),
+                buckets: Vec::new(),
+            },
+        });
+        timelines.insert(id, timeline);
+    }
+
+    if !movies.is_empty() {
+        // width_bucket puts `to` itself in bucket n + 1, it belongs to the last
+        let rows = sql_query(format!(
+            "SELECT movie, bucket, count(*) AS count, (array_agg(id ORDER BY time, id))[1] AS id, \
+             extract(epoch FROM min(time))::float8 AS time \
+             FROM (SELECT range.movie, frame.id, frame.time, \
+             least(width_bucket(extract(epoch FROM frame.time)::float8, \
+             range.range_start, range.range_end, $4), $4) AS bucket \
+             FROM unnest($1, $2, $3) AS range(movie, range_start, range_end) \
+             JOIN {table} AS frame ON frame.{series} = range.movie \
+             AND frame.time >= to_timestamp(range.range_start) AT TIME ZONE 'UTC' \
+             AND frame.time <= to_timestamp(range.range_end) AT TIME ZONE 'UTC') AS frames \
+             GROUP BY movie, bucket",
+            table = table,
+            series = series
+        ))
+        .bind::<Array<Int4>, _>(&movies)
+        .bind::<Array<Double>, _>(&starts)
+        .bind::<Array<Double>, _>(&ends)
+        .bind::<Int4, _>(n)
+        .load::<BucketRow>(conn)?;
+
+        for row in rows {
+            let bucket = match timelines.get_mut(&row.movie) {
+                Some(Ok(timeline)) => timeline.buckets.get_mut((row.bucket - 1) as usize),
+                _ => None,
+            };
+            if let Some(bucket) = bucket {
+                bucket.count = row.count;
+                bucket.frame = Some(Frame {
+                    id: row.id,
+                    time: row.time,
+                });
+            }
+        }
+    }
+
+    Ok(ids
+        .iter()
+        .filter_map(|id| timelines.remove(id).map(|timeline| (*id, timeline)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(from: Option<f64>, to: Option<f64>, buckets: Option<i32>) -> TimelineQuery {
+        TimelineQuery { from, to, buckets }
+    }
+
+    #[test]
+    fn checked_queries() {
+        assert_eq!(query(None, None, None).buckets().unwrap(), DEFAULT_BUCKETS);
+        assert_eq!(query(Some(0.0), Some(1.0), Some(3)).buckets().unwrap(), 3);
+        assert_eq!(
+            query(Some(2.0), None, Some(MAX_BUCKETS)).buckets().unwrap(),
+            MAX_BUCKETS
+        );
+
+        for invalid in &[
+            query(None, None, Some(0)),
+            query(None, None, Some(MAX_BUCKETS + 1)),
+            query(Some(1.0), Some(1.0), None),
+            query(Some(2.0), Some(1.0), None),
+            query(Some(f64::NAN), Some(1.0), None),
+            query(Some(f64::NAN), None, None),
+            query(Some(f64::NEG_INFINITY), Some(1.0), None),
+            query(Some(0.0), Some(f64::INFINITY), None),
+            query(None, Some(f64::INFINITY), None),
+        ] {
+            let error = invalid.buckets().unwrap_err();
+            assert_eq!(error.code(), "INVALID_PARAMETER");
+            assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let defaults = query(None, None, None);
+        assert_eq!(
+            defaults.range(Some(0.0), Some(30.0)).unwrap(),
+            Some((0.0, 30.0))
+        );
+        assert_eq!(
+            defaults.range(Some(5.0), Some(5.0)).unwrap(),
+            Some((5.0, 6.0))
+        );
+        assert_eq!(defaults.range(None, None).unwrap(), None);
+        let from = query(Some(10.0), None, None);
+        assert_eq!(
+            from.range(Some(0.0), Some(30.0)).unwrap(),
+            Some((10.0, 30.0))
+        );
+        assert_eq!(from.range(None, None).unwrap(), None);
+        let error = from.range(Some(0.0), Some(5.0)).unwrap_err();
+        assert_eq!(error.code(), "INVALID_PARAMETER");
+        let both = query(Some(10.0), Some(20.0), None);
+        assert_eq!(both.range(None, None).unwrap(), Some((10.0, 20.0)));
+    }
+
+    #[test]
+    fn errors() {
+        let error = TimelineError::NotFound("ColorMovie", 3);
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+        let error = error.into_field_error();
+        assert_eq!(error.message(), "ColorMovie 3 not found");
+        assert_eq!(error.extensions(), &graphql_value!({ "code": "NOT_FOUND" }));
+
+        let error = TimelineError::from(BlockingError::Canceled);
+        assert_eq!(error.code(), "EXECUTOR_UNAVAILABLE");
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}